@@ -0,0 +1,255 @@
+// Framed connection to the remote endpoint
+//
+// A Channel exchanges protocol frames over a byte stream. Frames travel in
+// cleartext until a cipher is installed at the end of the Noise handshake,
+// afterwards the stream of encoded frames is cut into chunks and every chunk
+// is sealed with ChaCha20-Poly1305 inside a Sealed frame. The receiver opens
+// the Sealed frames and reassembles inner frames out of the plaintext, so the
+// encryption is transparent to both handshake and flow code.
+
+use crate::protocol::{DecodeError, Frame, FrameReader, encode, write_frame};
+use anyhow::{Result, bail};
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::os::fd::{AsFd, BorrowedFd};
+
+/// Largest Noise transport message
+pub const MAX_SEALED_LEN: usize = 65535;
+// authentication tag appended to every sealed chunk
+const TAG_LEN: usize = 16;
+// largest plaintext sealed in a single chunk
+const MAX_CHUNK_LEN: usize = MAX_SEALED_LEN - TAG_LEN;
+
+/// Byte stream a Channel can be polled on
+pub trait Stream: Read + Write + AsFd {
+    /// True if received data is already buffered in userspace, where
+    /// polling the file descriptor cannot see it
+    fn buffered(&mut self) -> bool {
+        false
+    }
+
+    /// True if written data is still buffered in userspace, waiting for
+    /// the socket to be writable
+    fn wants_write(&self) -> bool {
+        false
+    }
+
+    /// Value unique to this connection and known to both endpoints only
+    /// (e.g. a TLS exporter), empty if the stream has none
+    fn binding(&self) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
+
+impl Stream for TcpStream {}
+
+struct Cipher {
+    transport: snow::TransportState,
+    // inner frames, reassembled from opened chunks
+    plain: FrameReader,
+    // encoding of outgoing inner frames
+    plain_out: Vec<u8>,
+    sealed: Vec<u8>,
+    // hash of the Noise handshake which established the keys
+    handshake_hash: Vec<u8>,
+}
+
+pub struct Channel<S> {
+    stream: S,
+    // frames as received from the stream
+    reader: FrameReader,
+    cipher: Option<Cipher>,
+    // encoding of outgoing frames
+    out: Vec<u8>,
+}
+
+impl<S: Read + Write> Channel<S> {
+    /// Cleartext channel accepting frame bodies up to `max_len` bytes
+    pub fn new(stream: S, max_len: usize) -> Self {
+        Channel {
+            stream,
+            reader: FrameReader::new(max_len),
+            cipher: None,
+            out: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// Change the maximum body length of received (inner) frames
+    pub fn set_max_len(&mut self, max_len: usize) {
+        match &mut self.cipher {
+            Some(cipher) => cipher.plain.set_max_len(max_len),
+            None => self.reader.set_max_len(max_len),
+        }
+    }
+
+    /// Seal every following frame with the given transport state,
+    /// `handshake_hash` identifies the Noise session it comes from
+    pub fn set_cipher(&mut self, transport: snow::TransportState, handshake_hash: &[u8]) {
+        // inner frames keep the current limit, Sealed frames have their own
+        let max_len = self.reader.max_len();
+        self.reader.set_max_len(MAX_SEALED_LEN);
+        self.cipher = Some(Cipher {
+            transport,
+            plain: FrameReader::new(max_len),
+            plain_out: Vec::new(),
+            sealed: vec![0; MAX_SEALED_LEN],
+            handshake_hash: handshake_hash.to_vec(),
+        });
+    }
+
+    /// Queue a frame, nothing is sent until `flush` is called
+    pub fn write(&mut self, frame: &Frame) -> Result<()> {
+        let Some(cipher) = &mut self.cipher else {
+            encode(frame, &mut self.out);
+            return Ok(());
+        };
+        cipher.plain_out.clear();
+        encode(frame, &mut cipher.plain_out);
+        for chunk in cipher.plain_out.chunks(MAX_CHUNK_LEN) {
+            let sz = cipher.transport.write_message(chunk, &mut cipher.sealed)?;
+            let ciphertext = &cipher.sealed[..sz];
+            write_frame(&mut self.out, &Frame::Sealed { ciphertext })?;
+        }
+        Ok(())
+    }
+
+    /// Send all queued frames
+    pub fn flush(&mut self) -> Result<()> {
+        self.stream.write_all(&self.out)?;
+        self.stream.flush()?;
+        self.out.clear();
+        Ok(())
+    }
+
+    /// Number of bytes queued and not sent yet
+    pub fn queued(&self) -> usize {
+        self.out.len()
+    }
+
+    /// Send a single frame
+    pub fn send(&mut self, frame: &Frame) -> Result<()> {
+        self.write(frame)?;
+        self.flush()
+    }
+
+    /// Perform a single read from the stream, return the number of bytes
+    /// read (0 on end of stream)
+    ///
+    /// Fails with `Interrupted` if the stream made progress without data,
+    /// e.g. received part of a TLS record: read again once readable.
+    pub fn fill(&mut self) -> std::io::Result<usize> {
+        self.reader.fill(&mut self.stream)
+    }
+
+    /// True if next_frame would return a frame
+    pub fn ready(&mut self) -> Result<bool, DecodeError> {
+        let Some(cipher) = &mut self.cipher else {
+            return self.reader.ready();
+        };
+        // open Sealed frames until a whole inner frame is available
+        while !cipher.plain.ready()? {
+            match self.reader.next_frame()? {
+                None => return Ok(false),
+                Some(Frame::Sealed { ciphertext }) => {
+                    let transport = &mut cipher.transport;
+                    cipher
+                        .plain
+                        .extend(MAX_SEALED_LEN, |buf| {
+                            transport.read_message(ciphertext, buf)
+                        })
+                        .map_err(|_| DecodeError::Unauthenticated)?;
+                }
+                // nothing is accepted in cleartext anymore
+                Some(_) => return Err(DecodeError::Unauthenticated),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Return next frame, if already received
+    pub fn next_frame(&mut self) -> Result<Option<Frame<'_>>, DecodeError> {
+        if !self.ready()? {
+            return Ok(None);
+        }
+        match &mut self.cipher {
+            Some(cipher) => cipher.plain.next_frame(),
+            None => self.reader.next_frame(),
+        }
+    }
+
+    /// Block until a whole frame is received
+    pub fn recv(&mut self) -> Result<Frame<'_>> {
+        while !self.ready()? {
+            match self.fill() {
+                Ok(0) => bail!("Remote endpoint closed the connection"),
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(self.next_frame()?.expect("whole frame available"))
+    }
+}
+
+impl<S: Stream> Channel<S> {
+    /// Switch the stream to non-blocking mode: reads and `try_flush` then
+    /// return as soon as the socket has nothing more to give or take
+    pub fn set_nonblocking(&self) -> std::io::Result<()> {
+        socket2::SockRef::from(&self.stream).set_nonblocking(true)
+    }
+
+    /// Value unique to this connection and known to both endpoints only:
+    /// binding of the stream followed by the Noise handshake hash, empty on
+    /// a cleartext channel
+    pub fn binding(&self) -> Result<Vec<u8>> {
+        let mut binding = self.stream.binding()?;
+        if let Some(cipher) = &self.cipher {
+            binding.extend_from_slice(&cipher.handshake_hash);
+        }
+        Ok(binding)
+    }
+
+    /// True if queued frames are waiting for the stream to be writable
+    pub fn wants_write(&self) -> bool {
+        !self.out.is_empty() || self.stream.wants_write()
+    }
+
+    /// Send as much of the queued frames as the stream accepts without
+    /// blocking, the rest is kept for the next call
+    pub fn try_flush(&mut self) -> Result<()> {
+        while !self.out.is_empty() {
+            match self.stream.write(&self.out) {
+                Ok(0) => bail!("Remote endpoint closed the connection"),
+                Ok(sz) => {
+                    self.out.drain(..sz);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        // e.g. TLS records the socket did not take yet
+        match self.stream.flush() {
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(()),
+            res => Ok(res?),
+        }
+    }
+}
+
+impl<S: AsFd> AsFd for Channel<S> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.stream.as_fd()
+    }
+}
@@ -1,23 +1,156 @@
+use crate::channel::Channel;
+use crate::dns::ResolvConf;
 use crate::flows;
-use crate::handshake;
+use crate::handshake::{self, Endpoint, Watchdog};
+use crate::netlink::Routes;
+use crate::parsing::{ClientConfig, Interface, Security};
+use crate::tls::Transport;
 
-use crate::tunif::Iface;
+use crate::tunif::{IfAddr, IfConfig, IfKind, Iface};
 use anyhow::{Result, bail};
-use std::net::{IpAddr, TcpStream};
+use std::net::{Ipv4Addr, Ipv6Addr, TcpStream};
+use std::time::Duration;
 
 pub fn execute_client(
-    ifname: String,
-    ifaddr: IpAddr,
-    netmask: u8,
-    remote: std::net::SocketAddr,
+    interface: Interface,
+    config: ClientConfig,
+    flow: flows::FlowConfig,
+    security: Security,
+    handshake_timeout: Option<Duration>,
 ) -> Result<()> {
-    let IpAddr::V4(tmp) = ifaddr else {
-        bail!("Cannot accept IPv6");
+    let stream = TcpStream::connect(config.remote)?;
+    let watchdog = handshake_timeout
+        .map(|timeout| Watchdog::new(&stream, timeout))
+        .transpose()?;
+    let local = Endpoint {
+        addrs: interface.addrs,
+        kind: interface.config.kind,
+        offload: interface.config.offload,
+        mtu: interface.config.mtu,
+        // no privileges to change routes or the resolver
+        host_config: !interface.config.attach,
+    };
+    let ans = Transport::new(stream, security.tls.as_ref()).and_then(|stream| {
+        let mut channel = Channel::new(stream, handshake::HANDSHAKE_MAX_LEN);
+        let negotiated = handshake::handler_client_handshake(
+            &mut channel,
+            &local,
+            security.noise.as_ref(),
+            security.psk.as_ref(),
+        )?;
+        Ok((channel, negotiated))
+    });
+    if watchdog.is_some_and(|watchdog| watchdog.expired()) {
+        bail!("HANDSHAKE error, server did not answer in time");
+    }
+    let (mut channel, (negotiated, setup)) = ans?;
+    // addresses might have been assigned by the server, the MTU might be
+    // lower than asked for
+    let ifname = interface.ifname;
+    let ifconfig = IfConfig {
+        mtu: negotiated.mtu,
+        ..interface.config
+    };
+    let mut iface = Iface::new(&ifname, &setup.addrs, &ifconfig)?;
+    // removed before the interface goes down
+    let pushed = setup.pushed;
+    let mut networks = split_default_routes(&pushed.routes);
+    if config.full_tunnel {
+        for network in full_tunnel_routes(&setup.addrs) {
+            if !networks.contains(&network) {
+                networks.push(network);
+            }
+        }
+    }
+    let mut routes = Routes::new()?;
+    // the connection to the server must not enter the tunnel
+    let server = config.remote.ip();
+    if networks.iter().any(|network| network.contains(server))
+        && let Some(route) = routes.pin_host(server)?
+    {
+        match route.gateway {
+            Some(gateway) => println!("Route {} via gateway {}", route.dst, gateway),
+            None => println!("Route {} kept off the tunnel", route.dst),
+        }
+    }
+    // an Ethernet link needs a next hop, the server
+    let gateways: Vec<_> = match ifconfig.kind {
+        IfKind::Tun => Vec::new(),
+        IfKind::Tap => setup.server_addrs.iter().map(|addr| addr.addr).collect(),
+    };
+    let existing = routes.add_all(iface.index(), &networks, &gateways)?;
+    for network in &networks {
+        if existing.contains(network) {
+            println!("Route {} already exists, left alone", network);
+        } else {
+            println!("Route {} via {}", network, ifname);
+        }
+    }
+    if let Some(path) = config.resolv_conf.filter(|_| !pushed.dns.is_empty()) {
+        iface.set_dns(Box::new(ResolvConf::new(path)), &pushed.dns)?;
+        for server in &pushed.dns.servers {
+            println!("DNS server: {}", server);
+        }
+        if !pushed.dns.domains.is_empty() {
+            println!("DNS search domains: {}", pushed.dns.domains.join(" "));
+        }
+    }
+    println!(
+        "Protocol version {}, capabilities: {:?}, MTU {}",
+        negotiated.version, negotiated.capabilities, negotiated.mtu
+    );
+    let flow = flows::FlowConfig {
+        capabilities: negotiated.capabilities,
+        mtu: negotiated.mtu,
+        ..flow
     };
-    let mut iface = Iface::new(&ifname, tmp, netmask)?;
-    let mut stream = TcpStream::connect(remote)?;
-    handshake::handler_client_handshake(&mut stream, &ifaddr, netmask)?;
     let mut sigfile = crate::signals::spawn_sig_handler()?;
-    flows::handle_flow(&mut stream, &mut iface, &mut sigfile)?;
+    flows::handle_flow(&mut channel, &mut iface, &mut sigfile, &flow)?;
     Ok(())
 }
+
+/// Routes covering the whole address space of every family of `addrs`
+///
+/// Two halves rather than a default route: they take precedence over the
+/// existing default route without replacing it.
+pub fn full_tunnel_routes(addrs: &[IfAddr]) -> Vec<IfAddr> {
+    let mut routes = Vec::new();
+    if addrs.iter().any(|addr| addr.addr.is_ipv4()) {
+        for half in [Ipv4Addr::UNSPECIFIED, Ipv4Addr::new(128, 0, 0, 0)] {
+            routes.push(IfAddr {
+                addr: half.into(),
+                netmask: 1,
+            });
+        }
+    }
+    if addrs.iter().any(|addr| addr.addr.is_ipv6()) {
+        for half in [
+            Ipv6Addr::UNSPECIFIED,
+            Ipv6Addr::new(0x8000, 0, 0, 0, 0, 0, 0, 0),
+        ] {
+            routes.push(IfAddr {
+                addr: half.into(),
+                netmask: 1,
+            });
+        }
+    }
+    routes
+}
+
+/// `networks` with every default route replaced by the two halves of the
+/// address space of its family, see `full_tunnel_routes`
+pub fn split_default_routes(networks: &[IfAddr]) -> Vec<IfAddr> {
+    let mut routes = Vec::new();
+    for network in networks {
+        let split = match network.netmask {
+            0 => full_tunnel_routes(&[*network]),
+            _ => vec![*network],
+        };
+        for route in split {
+            if !routes.contains(&route) {
+                routes.push(route);
+            }
+        }
+    }
+    routes
+}
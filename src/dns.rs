@@ -0,0 +1,113 @@
+// DNS configuration pushed by the server and applied by the client
+//
+// The client hands the pushed name servers and search domains to a backend
+// changing the system resolver configuration, and restores the previous
+// configuration when the interface goes away. The only backend so far
+// rewrites resolv.conf: the pushed entries come first, so they take
+// precedence, followed by the previous content.
+
+use anyhow::{Context, Result, bail};
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+/// Maximum number of name servers the server pushes
+pub const MAX_DNS_SERVERS: usize = 8;
+/// Maximum number of search domains the server pushes
+pub const MAX_DNS_DOMAINS: usize = 8;
+/// Longest domain name
+pub const MAX_DOMAIN_LEN: usize = 253;
+
+pub const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+// first line of the files written by the resolv.conf backend
+const MANAGED_HEADER: &str = "# Generated by rust-tcp-vpn";
+
+/// Name servers and search domains of the tunnel
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DnsConfig {
+    pub servers: Vec<IpAddr>,
+    pub domains: Vec<String>,
+}
+
+impl DnsConfig {
+    pub fn is_empty(&self) -> bool {
+        self.servers.is_empty() && self.domains.is_empty()
+    }
+}
+
+/// True if `name` is a domain name safe to write in a resolver
+/// configuration: letters, digits, '-', '_' and '.' only
+pub fn valid_domain(name: &str) -> bool {
+    (1..=MAX_DOMAIN_LEN).contains(&name.len())
+        && name
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || b"-_.".contains(&c))
+}
+
+/// System resolver configuration changed while the tunnel is up
+pub trait DnsBackend: Send {
+    /// Add `config`, for interface `ifname`, to the current configuration
+    fn apply(&mut self, ifname: &str, config: &DnsConfig) -> Result<()>;
+    /// Restore the configuration found by `apply`
+    fn restore(&mut self) -> Result<()>;
+}
+
+/// Backend rewriting a resolv.conf file
+pub struct ResolvConf {
+    path: PathBuf,
+    // content before `apply`, None if not applied
+    saved: Option<String>,
+}
+
+impl ResolvConf {
+    pub fn new(path: PathBuf) -> Self {
+        ResolvConf { path, saved: None }
+    }
+}
+
+impl DnsBackend for ResolvConf {
+    fn apply(&mut self, ifname: &str, config: &DnsConfig) -> Result<()> {
+        if self.saved.is_some() {
+            bail!(
+                "DNS configuration already applied to {}",
+                self.path.display()
+            );
+        }
+        let previous = match std::fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Cannot read {}", self.path.display()));
+            }
+        };
+        let mut text = format!("{} for {}\n", MANAGED_HEADER, ifname);
+        for server in &config.servers {
+            text += &format!("nameserver {}\n", server);
+        }
+        if !config.domains.is_empty() {
+            text += &format!("search {}\n", config.domains.join(" "));
+        }
+        for line in previous.lines() {
+            let keyword = line.split_whitespace().next();
+            // the resolver only uses the last search list
+            if !config.domains.is_empty() && matches!(keyword, Some("search" | "domain")) {
+                text += &format!("#{}\n", line);
+            } else {
+                text += &format!("{}\n", line);
+            }
+        }
+        // written in place, the file may be a symbolic link
+        std::fs::write(&self.path, text)
+            .with_context(|| format!("Cannot write {}", self.path.display()))?;
+        self.saved = Some(previous);
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        let Some(previous) = self.saved.take() else {
+            return Ok(());
+        };
+        std::fs::write(&self.path, previous)
+            .with_context(|| format!("Cannot restore {}", self.path.display()))
+    }
+}
@@ -1,165 +1,461 @@
-use crate::tunif::Iface;
+use crate::channel::{Channel, Stream};
+use crate::protocol::{Capabilities, ExitReason, Frame, max_body_len};
+use crate::sequence::{SeqTracker, Verdict};
+use crate::tunif::{DEFAULT_MTU, IfKind, TunDevice, max_packet_len};
 use anyhow::{Result, anyhow, bail};
 use nix::poll::PollFd;
 use nix::poll::PollFlags;
 use nix::poll::PollTimeout;
 use nix::poll::poll;
-use std::io::{BufReader, BufWriter, Read, Write};
-use std::net::TcpStream;
+use std::io::{ErrorKind, Read, Write};
 use std::os::fd::AsFd;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// stop reading the virtual interface while this many bytes are waiting for
+// the remote endpoint to accept them
+const MAX_QUEUED: usize = 64 * 1024;
+// how long queued packets (e.g. the exit packet) may take to leave once the
+// flow terminates
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Protocol violations detected since process start
+static PROTOCOL_VIOLATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of protocol violations committed by remote endpoints so far
+pub fn protocol_violations() -> u64 {
+    PROTOCOL_VIOLATIONS.load(Ordering::Relaxed)
+}
+
+// Packets read from the interface and dropped for exceeding the MTU
+static OVERSIZED_PACKETS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of local packets dropped so far for being larger than the MTU
+/// agreed on with the remote endpoint
+pub fn oversized_packets() -> u64 {
+    OVERSIZED_PACKETS.load(Ordering::Relaxed)
+}
+
+/// Tunables of `handle_flow`
+#[derive(Debug, Clone)]
+pub struct FlowConfig {
+    /// Interval between two Ping frames, None disables keepalive
+    pub keepalive: Option<Duration>,
+    /// Give up if nothing is received from the remote endpoint for this
+    /// long, None waits forever
+    pub dead_peer_timeout: Option<Duration>,
+    /// Tear the session down when a replayed data frame is received,
+    /// otherwise replayed frames are just discarded
+    pub reject_replay: bool,
+    /// Features negotiated with the remote endpoint during the handshake
+    pub capabilities: Capabilities,
+    /// MTU agreed on with the remote endpoint during the handshake, bounds
+    /// the packets exchanged
+    pub mtu: u32,
+}
+
+impl Default for FlowConfig {
+    fn default() -> Self {
+        FlowConfig {
+            keepalive: Some(Duration::from_secs(10)),
+            dead_peer_timeout: Some(Duration::from_secs(30)),
+            reject_replay: false,
+            capabilities: Capabilities::all(),
+            mtu: DEFAULT_MTU,
+        }
+    }
+}
+
+/// Why `handle_flow` terminated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlowExit {
+    // local signal, exit packet sent to remote endpoint
+    Local,
+    // remote endpoint sent an exit packet
+    Remote(ExitReason),
+    // remote endpoint violated the protocol, exit packet sent to it
+    ProtocolViolation,
+    // nothing received within the dead peer timeout
+    PeerTimeout,
+    // remote endpoint replayed a data frame, exit packet sent to it
+    Replay,
+}
 
 enum Status {
     // continue
     Continue,
-    // regular exit
-    ExitOk,
+    // remote endpoint sent exit packet
+    Exit(ExitReason),
+    // remote endpoint sent a Ping, carries its timestamp
+    Ping(u64),
+    // remote endpoint answered our Ping, carries its timestamp
+    Pong(u64),
+    // data frame with an already seen counter, carries the counter
+    Replay(u64),
+    // remote endpoint sent a frame not valid in this phase
+    Unexpected(&'static str),
 }
 
-fn send_exit_pkt(stream: &mut impl std::io::Write, exit_reason: u32) -> Result<()> {
-    // build packet
-    // exit packet: type 2
-    stream.write_all(&2_u32.to_be_bytes())?;
-    // exit reason, only 0 in currently valid
-    stream.write_all(&exit_reason.to_be_bytes())?;
-    // send packet
-    stream.flush()?;
-    Ok(())
+// Liveness tracking of the remote endpoint
+struct Keepalive {
+    config: FlowConfig,
+    // Ping timestamps are microseconds since this instant
+    start: Instant,
+    // last time anything was received from the remote endpoint
+    last_rx: Instant,
+    next_ping: Option<Instant>,
+}
+
+impl Keepalive {
+    fn new(config: &FlowConfig) -> Self {
+        let now = Instant::now();
+        let mut config = config.clone();
+        // the remote endpoint would neither answer Ping frames nor send its
+        // own, silence does not mean it is dead
+        if !config.capabilities.contains(Capabilities::KEEPALIVE) {
+            config.keepalive = None;
+            config.dead_peer_timeout = None;
+        }
+        Keepalive {
+            start: now,
+            last_rx: now,
+            next_ping: config.keepalive.map(|interval| now + interval),
+            config,
+        }
+    }
+
+    fn timestamp(&self, now: Instant) -> u64 {
+        now.duration_since(self.start).as_micros() as u64
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.config
+            .dead_peer_timeout
+            .map(|timeout| self.last_rx + timeout)
+    }
+
+    // wait at most until the next timer expires
+    fn poll_timeout(&self, now: Instant) -> PollTimeout {
+        let next = match (self.next_ping, self.deadline()) {
+            (Some(a), Some(b)) => a.min(b),
+            (a, b) => match a.or(b) {
+                Some(next) => next,
+                None => return PollTimeout::NONE,
+            },
+        };
+        // round up, not to wake up just before the timer expires
+        let millis = next
+            .saturating_duration_since(now)
+            .as_micros()
+            .div_ceil(1000);
+        PollTimeout::try_from(millis).unwrap_or(PollTimeout::MAX)
+    }
+
+    fn expired(&self, now: Instant) -> bool {
+        self.deadline().is_some_and(|deadline| now >= deadline)
+    }
+
+    // return the timestamp to be sent if a Ping is due
+    fn ping_due(&mut self, now: Instant) -> Option<u64> {
+        let (Some(next), Some(interval)) = (self.next_ping, self.config.keepalive) else {
+            return None;
+        };
+        if now < next {
+            return None;
+        }
+        self.next_ping = Some(now + interval);
+        Some(self.timestamp(now))
+    }
+
+    fn rtt(&self, now: Instant, timestamp: u64) -> Duration {
+        Duration::from_micros(self.timestamp(now).saturating_sub(timestamp))
+    }
 }
 
-fn handle_local2remote_pkt(
-    iffile: &mut Iface,
-    stream: &mut impl std::io::Write,
+// Queue the exit packet and wait for it to leave
+fn send_exit_pkt<S: Stream>(channel: &mut Channel<S>, exit_reason: ExitReason) -> Result<()> {
+    channel.write(&Frame::Exit {
+        reason: exit_reason.code(),
+    })?;
+    drain_queued(channel)
+}
+
+// packets longer than `max_len` are dropped, `buffer` must be longer to
+// tell them apart
+fn handle_local2remote_pkt<S: Read + Write>(
+    iffile: &mut impl TunDevice,
+    channel: &mut Channel<S>,
     counter: &mut u64,
     buffer: &mut [u8],
+    max_len: usize,
 ) -> Result<Status> {
-    let sz = iffile.as_ref().read(buffer)?;
+    let sz = iffile.recv(buffer)?;
     if sz == 0 {
         bail!("UNEXPECTED EMPTY PACKET from Virtual interface!");
     }
+    // the remote endpoint would refuse it
+    if sz > max_len {
+        let count = OVERSIZED_PACKETS.fetch_add(1, Ordering::Relaxed) + 1;
+        // a wrong MTU drops many packets, log some of them only
+        if count.is_power_of_two() {
+            eprintln!(
+                "Dropped packet #{} of {} bytes, larger than the {} bytes allowed by the MTU",
+                count, sz, max_len
+            );
+        }
+        return Ok(Status::Continue);
+    }
     *counter += 1;
-    // build packet
-    // data packet: type 1
-    stream.write_all(&1_u32.to_be_bytes())?;
-    // pkt length
-    stream.write_all(&(sz as u32).to_be_bytes())?;
-    // counter
-    stream.write_all(&counter.to_be_bytes())?;
-    // network packet
-    stream.write_all(&buffer[..sz])?;
-    // send packet
-    stream.flush()?;
+    // queue packet, sent as soon as the remote endpoint accepts it
+    channel.write(&Frame::Data {
+        counter: *counter,
+        payload: &buffer[..sz],
+    })?;
     // Everything Ok, continue
     Ok(Status::Continue)
 }
 
 fn handle_remote2local_pkt(
-    iffile: &mut Iface,
-    stream: &mut impl std::io::BufRead,
-    buffer: &mut [u8],
+    iffile: &mut impl TunDevice,
+    tracker: &mut SeqTracker,
+    frame: Frame,
 ) -> Result<Status> {
-    // read packet type
-    let mut pkt_type: [u8; 4] = [0; 4];
-    stream.read_exact(&mut pkt_type)?;
-    let pkt_type = u32::from_be_bytes(pkt_type);
-    match pkt_type {
-        1 => {
-            let mut pkt_len: [u8; 4] = [0; 4];
-            stream.read_exact(&mut pkt_len)?;
-            let pkt_len: u32 = u32::from_be_bytes(pkt_len);
-            let mut counter: [u8; 8] = [0; 8];
-            stream.read_exact(&mut counter)?;
-            let _counter = u64::from_be_bytes(counter);
-            // counter is unused now
-            stream.read_exact(&mut buffer[0..(pkt_len as usize)])?;
-            // https://doc.rust-lang.org/std/fs/struct.File.html#method.write_all_at-1
-            iffile.as_ref().write_all(&buffer[0..(pkt_len as usize)])?;
-            Ok(Status::Continue)
-        }
-        2 => {
-            let mut exit_reason: [u8; 4] = [0; 4];
-            stream.read_exact(&mut exit_reason)?;
-            let exit_reason: u32 = u32::from_be_bytes(exit_reason);
-            if exit_reason != 0 {
-                bail!("Unknown exit reason code {} in VPN protocol", exit_reason);
-            } else {
-                // terminate VPN protocol
-                Ok(Status::ExitOk)
+    match frame {
+        Frame::Data { counter, payload } => match tracker.check(counter) {
+            Verdict::InOrder | Verdict::Late => {
+                iffile.send(payload)?;
+                Ok(Status::Continue)
             }
+            // never inject the same packet twice
+            Verdict::Replay => Ok(Status::Replay(counter)),
+        },
+        Frame::Ping { timestamp } => Ok(Status::Ping(timestamp)),
+        Frame::Pong { timestamp } => Ok(Status::Pong(timestamp)),
+        // terminate VPN protocol
+        Frame::Exit { reason } => Ok(Status::Exit(ExitReason::from_code(reason))),
+        // handshake frames are not valid anymore
+        _ => Ok(Status::Unexpected(frame.name())),
+    }
+}
+
+// Give the queued packets a last chance to leave, without waiting forever
+// for a remote endpoint that stopped reading
+fn drain_queued<S: Stream>(channel: &mut Channel<S>) -> Result<()> {
+    let deadline = Instant::now() + DRAIN_TIMEOUT;
+    channel.try_flush()?;
+    while channel.wants_write() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            bail!(
+                "Remote endpoint is not reading, {} bytes not sent",
+                channel.queued()
+            );
         }
-        _ => {
-            bail!("Unknown packet type: {} (only 1 valid)", pkt_type);
-        }
+        let timeout = PollTimeout::try_from(left.as_millis()).unwrap_or(PollTimeout::MAX);
+        poll(
+            &mut [PollFd::new(channel.as_fd(), PollFlags::POLLOUT)],
+            timeout,
+        )?;
+        channel.try_flush()?;
     }
+    Ok(())
+}
+
+// Record the violation and notify the remote endpoint before giving up
+fn protocol_violation<S: Stream>(
+    channel: &mut Channel<S>,
+    err: impl std::fmt::Display,
+) -> Result<FlowExit> {
+    let count = PROTOCOL_VIOLATIONS.fetch_add(1, Ordering::Relaxed) + 1;
+    eprintln!("Protocol violation #{} by remote endpoint: {}", count, err);
+    send_exit_pkt(channel, ExitReason::ProtocolViolation)?;
+    Ok(FlowExit::ProtocolViolation)
 }
 
 // https://docs.rs/nix/0.28.0/nix/poll/struct.PollFd.html
 // sigfile has been generated by crate::signals::spawn_sig_handler
 // and is filled with new data everytime a signal is received
 //
-// Return true if exits because received exit packet from remote
-// endpoint (or in case of remote stream error), return false if
-// it exits because of local signal
+// Return FlowExit::Local if it exits because of local signal, otherwise
+// the flow has been terminated by the remote endpoint, either regularly,
+// because it violated the protocol or because it stopped answering
+//
+// Ping frames are sent every config.keepalive, and the flow gives up if
+// nothing at all is received for config.dead_peer_timeout
 //
-// Return Err in case of other errors
-pub fn handle_flow(
-    stream: &mut TcpStream,
-    iffile: &mut Iface,
+// Both directions are serviced independently: packets coming from the
+// virtual interface are forwarded as soon as the interface is readable,
+// regardless of any traffic from the remote endpoint, and vice versa. The
+// socket is made non-blocking: packets the remote endpoint does not accept
+// yet are queued and sent once the socket is writable, the interface is not
+// read while too many of them are waiting
+//
+// Counters of received data frames are validated, and per session
+// statistics about lost, reordered and replayed frames are printed when
+// the flow terminates
+//
+// The channel carries on from the handshake, including frames already
+// received and encryption state
+//
+// Return Err in case of other errors (including remote stream errors)
+pub fn handle_flow<S: Stream>(
+    channel: &mut Channel<S>,
+    iffile: &mut impl TunDevice,
     sigfile: &mut std::fs::File,
-) -> Result<bool> {
-    let mut buffer = [0; 4096];
-    // split both socket ends
-    let mut ostream = BufWriter::with_capacity(64 + 4096, stream.try_clone()?);
-    let mut istream = BufReader::with_capacity(64 + 4096, stream.try_clone()?);
+    config: &FlowConfig,
+) -> Result<FlowExit> {
+    let mut tracker = SeqTracker::new();
+    let ans = flow_loop(channel, iffile, sigfile, config, &mut tracker);
+    println!("Session statistics: {}", tracker.stats());
+    ans
+}
+
+fn flow_loop<S: Stream>(
+    channel: &mut Channel<S>,
+    iffile: &mut impl TunDevice,
+    sigfile: &mut std::fs::File,
+    config: &FlowConfig,
+    tracker: &mut SeqTracker,
+) -> Result<FlowExit> {
+    let kind = if config.capabilities.contains(Capabilities::ETHERNET) {
+        IfKind::Tap
+    } else {
+        IfKind::Tun
+    };
+    let offload = config.capabilities.contains(Capabilities::OFFLOAD);
+    let max_len = max_packet_len(kind, config.mtu, offload);
+    // one more byte to spot longer packets, e.g. for another session of a
+    // shared interface with a larger MTU
+    let mut buffer = vec![0; max_len + 1];
+    channel.set_nonblocking()?;
+    // larger frames are rejected before being read
+    channel.set_max_len(max_body_len(max_len));
     // count how many packets are sent?
     let mut counter = 0;
+    let mut keepalive = Keepalive::new(config);
 
     loop {
-        let mut fds = [sigfile.as_fd(), stream.as_fd(), iffile.as_fd()]
-            .map(|fd| PollFd::new(fd, PollFlags::POLLIN));
+        // frames might be already buffered (e.g. received together with the
+        // end of the handshake), don't wait for the socket in that case
+        let buffered = match channel.ready() {
+            Ok(buffered) => buffered,
+            Err(err) => return protocol_violation(channel, err),
+        };
+        // same for data decrypted by the stream (e.g. TLS) but not read yet
+        let stream_buffered = channel.get_mut().buffered();
+        let timeout = if buffered || stream_buffered {
+            PollTimeout::ZERO
+        } else {
+            keepalive.poll_timeout(Instant::now())
+        };
+        let tcp_events = if channel.wants_write() {
+            PollFlags::POLLIN | PollFlags::POLLOUT
+        } else {
+            PollFlags::POLLIN
+        };
+        let if_events = if channel.queued() < MAX_QUEUED {
+            PollFlags::POLLIN
+        } else {
+            PollFlags::empty()
+        };
+        let mut fds = [
+            PollFd::new(sigfile.as_fd(), PollFlags::POLLIN),
+            PollFd::new(channel.as_fd(), tcp_events),
+            PollFd::new(iffile.as_fd(), if_events),
+        ];
         // https://docs.rs/nix/0.28.0/nix/poll/fn.poll.html
-        let ret = poll(&mut fds, PollTimeout::NONE)?;
-        if ret <= 0 {
-            bail!("Non positive nix::poll::poll");
+        let ret = poll(&mut fds, timeout)?;
+        if ret < 0 {
+            bail!("Negative nix::poll::poll");
         }
         let [pipe_fd, tcp_fd, if_fd] = fds;
         let b = pipe_fd
             .any()
             .ok_or(anyhow!("ERROR: pipe_fd.any() returned None!"))?;
+        // check tcp connection
+        let if_flag = if_fd
+            .any()
+            .ok_or(anyhow!("ERROR: if_fd.any() returned None!"))?;
+        // writable only is not enough to read
+        let tcp_flag = tcp_fd
+            .revents()
+            .ok_or(anyhow!("ERROR: tcp_fd.revents() returned None!"))?
+            .intersects(PollFlags::POLLIN | PollFlags::POLLHUP | PollFlags::POLLERR)
+            || stream_buffered;
         if b {
             // consume pending signal data
             crate::signals::consume_sigpipe(sigfile);
-            let exit_reason = 0; // normal exit
-            if let Err(err) = send_exit_pkt(&mut ostream, exit_reason) {
+            if let Err(err) = send_exit_pkt(channel, ExitReason::Normal) {
                 bail!(
                     "Anomalous error occurred while sending exit packet: {}",
                     err
                 );
             }
-            return Ok(false);
+            return Ok(FlowExit::Local);
         }
-        // check tcp connection
-        let if_flag = if_fd
-            .any()
-            .ok_or(anyhow!("ERROR: if_fd.any() returned None!"))?;
-        let tcp_flag = tcp_fd
-            .any()
-            .ok_or(anyhow!("ERROR: tcp_fd.any() returned None!"))?;
-        if tcp_flag {
-            loop {
-                if let Status::ExitOk = handle_remote2local_pkt(iffile, &mut istream, &mut buffer)?
-                {
-                    // remote endpoint exited
-                    println!("Remote exit!");
-                    return Ok(true);
+        // timers
+        let now = Instant::now();
+        if keepalive.expired(now) && !tcp_flag {
+            eprintln!("Remote endpoint is not answering, giving up");
+            send_exit_pkt(channel, ExitReason::PeerTimeout)?;
+            return Ok(FlowExit::PeerTimeout);
+        }
+        if let Some(timestamp) = keepalive.ping_due(now) {
+            channel.write(&Frame::Ping { timestamp })?;
+        }
+        // remote -> local
+        if tcp_flag || buffered {
+            if tcp_flag {
+                match channel.fill() {
+                    Ok(0) => bail!("Remote endpoint closed the connection"),
+                    Ok(_) => {}
+                    // nothing to read after all, or e.g. partial TLS record:
+                    // the rest comes with another poll
+                    Err(err)
+                        if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => {
+                    }
+                    Err(err) => return Err(err.into()),
                 }
-                // https://doc.rust-lang.org/std/io/struct.BufReader.html#method.buffer
-                if istream.buffer().is_empty() {
-                    if if_flag {
-                        handle_local2remote_pkt(iffile, &mut ostream, &mut counter, &mut buffer)?;
+                keepalive.last_rx = Instant::now();
+            }
+            // handle every frame received so far, partial frames are
+            // completed by following reads
+            loop {
+                let frame = match channel.next_frame() {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break,
+                    Err(err) => return protocol_violation(channel, err),
+                };
+                match handle_remote2local_pkt(iffile, tracker, frame)? {
+                    Status::Continue => {}
+                    Status::Ping(timestamp) => channel.write(&Frame::Pong { timestamp })?,
+                    Status::Replay(counter) => {
+                        eprintln!("Replayed data frame, counter: {}", counter);
+                        if config.reject_replay {
+                            send_exit_pkt(channel, ExitReason::Replay)?;
+                            return Ok(FlowExit::Replay);
+                        }
+                    }
+                    Status::Pong(timestamp) => {
+                        let rtt = keepalive.rtt(Instant::now(), timestamp);
+                        println!("RTT: {:.3} ms", rtt.as_secs_f64() * 1000.0);
+                    }
+                    Status::Exit(reason) => {
+                        // remote endpoint exited
+                        println!("Remote exit: {}", reason);
+                        return Ok(FlowExit::Remote(reason));
+                    }
+                    Status::Unexpected(name) => {
+                        let err = format!("unexpected {} frame", name);
+                        return protocol_violation(channel, err);
                     }
-                    break;
                 }
             }
         }
+        // local -> remote
+        if if_flag {
+            handle_local2remote_pkt(iffile, channel, &mut counter, &mut buffer, max_len)?;
+        }
+        channel.try_flush()?;
     }
 }
@@ -1,198 +1,516 @@
+use crate::channel::{Channel, Stream};
+use crate::dns::DnsConfig;
+use crate::leases::ClientId;
+use crate::noise::{self, NoiseKeys};
+use crate::pool::{AddressPool, Lease};
+use crate::protocol::{
+    Capabilities, Frame, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, STATUS_INTERFACE_MISMATCH,
+    STATUS_OK, STATUS_UNSUPPORTED_VERSION,
+};
+use crate::psk::{self, Psk};
+use crate::tunif::{DEFAULT_MTU, IfAddr, IfKind};
 use anyhow::{Result, bail};
-use std::io::{BufReader, BufWriter, Read, Write};
-use std::net::{IpAddr, Ipv4Addr, TcpStream};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
 
-const MAGIC: u32 = 0x12345678;
+// handshake frames are small, even with all the routes and DNS settings
+// pushed
+pub const HANDSHAKE_MAX_LEN: usize = 4096;
+/// Maximum number of routes the server pushes to clients
+pub const MAX_ROUTES: usize = 64;
+
+/// Deadline of a handshake
+///
+/// The connection is shut down if the watchdog is still alive when the
+/// timeout expires, so a peer that stalls (or sends one byte at a time)
+/// cannot hold the handshake forever: any blocked read or write fails.
+pub struct Watchdog {
+    // dropping it stops the watchdog thread
+    _cancel: mpsc::Sender<()>,
+    expired: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    pub fn new(sock: &TcpStream, timeout: Duration) -> Result<Self> {
+        let sock = sock.try_clone()?;
+        let (cancel, cancelled) = mpsc::channel::<()>();
+        let expired = Arc::new(AtomicBool::new(false));
+        let flag = expired.clone();
+        thread::spawn(move || {
+            if let Err(RecvTimeoutError::Timeout) = cancelled.recv_timeout(timeout) {
+                flag.store(true, Ordering::Relaxed);
+                let _ = sock.shutdown(Shutdown::Both);
+            }
+        });
+        Ok(Watchdog {
+            _cancel: cancel,
+            expired,
+        })
+    }
+
+    /// True if the connection has been shut down because of the timeout
+    pub fn expired(&self) -> bool {
+        self.expired.load(Ordering::Relaxed)
+    }
+}
+
+/// Configuration pushed by the server, to the clients supporting it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pushed {
+    // networks to route through the tunnel
+    pub routes: Vec<IfAddr>,
+    pub dns: DnsConfig,
+}
+
+impl Pushed {
+    // the part of the configuration the client can apply
+    fn supported(&self, capabilities: Capabilities) -> Pushed {
+        Pushed {
+            routes: if capabilities.contains(Capabilities::ROUTES) {
+                self.routes.clone()
+            } else {
+                Vec::new()
+            },
+            dns: if capabilities.contains(Capabilities::DNS) {
+                self.dns.clone()
+            } else {
+                DnsConfig::default()
+            },
+        }
+    }
+}
+
+/// Local end of the tunnel, as announced in the handshake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    // interface addresses, none for a client asking the server
+    pub addrs: Vec<IfAddr>,
+    pub kind: IfKind,
+    // packets carry a virtio-net header
+    pub offload: bool,
+    // MTU of the interface
+    pub mtu: u32,
+    // routes and DNS settings are pushed by a server or applied by a client,
+    // which an unprivileged client on an attached interface cannot do
+    pub host_config: bool,
+}
+
+// features describing the interface, both endpoints must announce them or
+// neither
+const INTERFACE: Capabilities = Capabilities::ETHERNET.union(Capabilities::OFFLOAD);
+
+impl Endpoint {
+    // features announced to the remote endpoint
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::all() - INTERFACE;
+        capabilities.set(Capabilities::ETHERNET, self.kind == IfKind::Tap);
+        capabilities.set(Capabilities::OFFLOAD, self.offload);
+        if !self.host_config {
+            capabilities.remove(Capabilities::ROUTES | Capabilities::DNS);
+        }
+        capabilities
+    }
+}
+
+// interface of an endpoint announcing `capabilities`, e.g. "TAP with offload"
+fn interface_of(capabilities: Capabilities) -> String {
+    let kind = if capabilities.contains(Capabilities::ETHERNET) {
+        IfKind::Tap
+    } else {
+        IfKind::Tun
+    };
+    if capabilities.contains(Capabilities::OFFLOAD) {
+        format!("{} with offload", kind)
+    } else {
+        kind.to_string()
+    }
+}
+
+/// Interface configuration of the client, chosen by it or pushed by the
+/// server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientSetup {
+    pub addrs: Vec<IfAddr>,
+    // interface addresses of the server
+    pub server_addrs: Vec<IfAddr>,
+    pub pushed: Pushed,
+}
+
+/// Outcome of the handshake, both endpoints agree on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiated {
+    pub version: u32,
+    // features supported by both endpoints
+    pub capabilities: Capabilities,
+    // smallest MTU of both interfaces
+    pub mtu: u32,
+}
+
+// MTU of the tunnel between a local interface with MTU `local` and a remote
+// endpoint announcing `remote`, for the `capabilities` in common
+fn agree_on_mtu(local: u32, capabilities: Capabilities, remote: Option<u32>) -> Result<u32> {
+    match (capabilities.contains(Capabilities::MTU), remote) {
+        (true, Some(remote)) => Ok(local.min(remote)),
+        (true, None) => bail!("HANDSHAKE error, MTU capability without MTU"),
+        // endpoints not announcing their MTU keep the default one
+        (false, _) => Ok(local.min(DEFAULT_MTU)),
+    }
+}
 
 // INITIAL HANDSHAKE:
-//      1. client send packet containing (ifaddr,netmask)
-//      2. server check received packet from client
-//      3. server sends its ifaddr
+//      0. (encrypted mode only) Noise key exchange, every following
+//         packet is encrypted
+//         (pre-shared key mode only) both endpoints prove the knowledge
+//         of the key, see psk.rs
+//      1. client send packet containing (versions,capabilities,ifaddrs,
+//         MTU), no ifaddrs to ask the server for them
+//      2. server check received packet from client, refuse the client if
+//         no protocol version is in common or if the interfaces cannot be
+//         mixed (TUN and TAP, offload or not), assign client ifaddrs from
+//         the pool if asked
+//      3. server sends (chosen version,capabilities,ifaddrs,assigned ifaddrs,
+//         routes, DNS settings and MTU if the client supports them), both
+//         endpoints use the smallest MTU
 //      4. client double check server if properties and send OK to server
 //      5. client can now bring interface UP
 //      6. server receive Ok from client
 //      7. server can now bring interface UP
 //      8. server and client can now exchange packets
-pub fn handler_server_handshake(
-    stream: &mut TcpStream,
-    ifaddr: &IpAddr,
-    netmask: u8,
-) -> Result<()> {
-    let ifaddr: &Ipv4Addr = match ifaddr {
-        IpAddr::V4(addr) => addr,
-        _ => {
-            bail!("Cannot accept IPv6");
-        }
-    };
-    // https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.try_clone
-    let ostream = stream.try_clone()?;
-    // https://doc.rust-lang.org/std/io/struct.BufWriter.html#method.with_capacity
-    let mut ostream = BufWriter::with_capacity(64, ostream);
-    // read stream
-    let istream = stream.try_clone()?;
-    let mut istream = BufReader::with_capacity(64, istream);
-
-    // classic netmask
-    let netmask = 0xFFFF_FFFFu32.wrapping_shl(32 - netmask as u32);
-    // local addr
-    let local_addr: u32 = u32::from_be_bytes(ifaddr.octets());
+//
+// The server also gets the interface addresses of the client, reserved in
+// the pool (if any) as long as the lease is kept. The `pushed` configuration
+// is sent to clients able to apply it. The client `identity` proven by the
+// transport (its TLS certificate) is superseded by its Noise key, if any;
+// the pool gives the same addresses to the same identity.
+pub fn handler_server_handshake<S: Stream>(
+    channel: &mut Channel<S>,
+    local: &Endpoint,
+    pool: Option<&Arc<AddressPool>>,
+    pushed: &Pushed,
+    identity: Option<ClientId>,
+    noise: Option<&NoiseKeys>,
+    psk: Option<&Psk>,
+) -> Result<(Negotiated, Lease)> {
+    // 0. key exchange
+    let mut identity = identity;
+    if let Some(keys) = noise {
+        identity = Some(ClientId::Noise(noise::server_handshake(channel, keys)?));
+    }
+    // 0. authentication
+    if let Some(psk) = psk {
+        psk::server_auth(channel, psk)?;
+    }
+
     // 2. parse first packet
-    parse_first_packet(&mut istream, netmask, local_addr)?;
-    // 3. send server ifaddr
-    send_server_ifaddr(&mut ostream, local_addr)?;
+    let (negotiated, lease, assigned) = parse_first_packet(channel, local, pool, identity)?;
+    // 3. send server ifaddrs
+    let pushed = pushed.supported(negotiated.capabilities);
+    send_server_ifaddr(channel, &negotiated, local, assigned, pushed)?;
     // 5 check client response
-    check_client_response(&mut istream)?;
+    check_client_response(channel)?;
 
-    Ok(())
+    Ok((negotiated, lease))
 }
 
-fn check_client_response(istream: &mut BufReader<TcpStream>) -> Result<()> {
-    let mut packet: [u8; 8] = [0; 8];
-    istream.read_exact(&mut packet)?;
-    let scan = packet.as_slice();
-    let (&pktid, scan): (&[u8; 4], _) = scan.split_first_chunk().unwrap();
-    let pktid = u32::from_be_bytes(pktid);
-    if 3 != pktid {
-        bail!("HANDSHAKE error, pktid: {} instead of {}", pktid, 3);
-    }
-    let (&status, _): (&[u8; 4], _) = scan.split_first_chunk().unwrap();
-    let status = u32::from_be_bytes(status);
-    if status != 0 {
+fn check_client_response<S: Read + Write>(channel: &mut Channel<S>) -> Result<()> {
+    let frame = channel.recv()?;
+    let Frame::HandshakeStatus { status } = frame else {
+        bail!(
+            "HANDSHAKE error, frame: {} instead of {}",
+            frame.name(),
+            "HandshakeStatus"
+        );
+    };
+    if status != STATUS_OK {
         bail!(
             "HANDSHAKE error, client status: {} instead of {}",
             status,
-            0
+            STATUS_OK
         );
     }
     Ok(())
 }
 
-fn send_server_ifaddr(ostream: &mut BufWriter<TcpStream>, local_addr: u32) -> Result<()> {
-    ostream.write_all(&2_u32.to_be_bytes())?;
-    ostream.write_all(&local_addr.to_be_bytes())?;
-    ostream.flush()?;
+fn send_server_ifaddr<S: Read + Write>(
+    channel: &mut Channel<S>,
+    negotiated: &Negotiated,
+    local: &Endpoint,
+    assigned: Vec<IfAddr>,
+    pushed: Pushed,
+) -> Result<()> {
+    let with_mtu = negotiated.capabilities.contains(Capabilities::MTU);
+    channel.send(&Frame::HelloReply {
+        version: negotiated.version,
+        capabilities: local.capabilities(),
+        addrs: local.addrs.clone(),
+        assigned,
+        routes: pushed.routes,
+        dns: pushed.dns.servers,
+        domains: pushed.dns.domains,
+        mtu: with_mtu.then_some(local.mtu),
+    })
+}
+
+// both endpoints must have one address of each family in use, in the same
+// subnet as the local one
+fn check_addresses(local_addrs: &[IfAddr], remote_addrs: &[IfAddr]) -> Result<()> {
+    for (i, remote) in remote_addrs.iter().enumerate() {
+        if remote_addrs[..i]
+            .iter()
+            .any(|other| other.addr.is_ipv4() == remote.addr.is_ipv4())
+        {
+            bail!("HANDSHAKE error, address: several remote {} family", remote);
+        }
+        if !local_addrs
+            .iter()
+            .any(|local| local.addr.is_ipv4() == remote.addr.is_ipv4())
+        {
+            bail!(
+                "HANDSHAKE error, address: remote {} has no local family",
+                remote
+            );
+        }
+    }
+    for local in local_addrs {
+        let Some(remote) = remote_addrs
+            .iter()
+            .find(|remote| remote.addr.is_ipv4() == local.addr.is_ipv4())
+        else {
+            bail!(
+                "HANDSHAKE error, address: local {} has no remote family",
+                local
+            );
+        };
+        if local.netmask != remote.netmask {
+            bail!(
+                "HANDSHAKE error, netmask: {} instead of {}",
+                remote.netmask,
+                local.netmask
+            );
+        }
+        if !local.is_peer(remote) {
+            bail!(
+                "HANDSHAKE error, address: local {} remote {}",
+                local.addr,
+                remote.addr
+            );
+        }
+    }
     Ok(())
 }
 
-fn parse_first_packet(
-    istream: &mut BufReader<TcpStream>,
-    netmask: u32,
-    local_addr: u32,
-) -> Result<()> {
-    let mut packet: [u8; 16] = [0; 16];
-    istream.read_exact(&mut packet)?;
-    let scan = packet.as_slice();
-    let (&found_magic, scan): (&[u8; 4], _) = scan.split_first_chunk().unwrap();
-    let found_magic = u32::from_be_bytes(found_magic);
-    if MAGIC != found_magic {
+// return the addresses assigned to the client too, if it asked for them
+fn parse_first_packet<S: Read + Write>(
+    channel: &mut Channel<S>,
+    local: &Endpoint,
+    pool: Option<&Arc<AddressPool>>,
+    identity: Option<ClientId>,
+) -> Result<(Negotiated, Lease, Vec<IfAddr>)> {
+    let frame = channel.recv()?;
+    let Frame::Hello {
+        min_version,
+        max_version,
+        capabilities,
+        addrs: remote_addrs,
+        mtu: remote_mtu,
+    } = frame
+    else {
         bail!(
-            "HANDSHAKE error, magic: {} instead of {}",
-            found_magic,
-            MAGIC
+            "HANDSHAKE error, frame: {} instead of {}",
+            frame.name(),
+            "Hello"
         );
-    }
-    let (&pktid, scan): (&[u8; 4], _) = scan.split_first_chunk().unwrap();
-    let pktid = u32::from_be_bytes(pktid);
-    if 1 != pktid {
-        bail!("HANDSHAKE error, pktid: {} instead of {}", pktid, 1);
-    }
-    let (&remote_addr, scan): (&[u8; 4], _) = scan.split_first_chunk().unwrap();
-    let remote_addr = u32::from_be_bytes(remote_addr);
-    let (&remote_netmask, _): (&[u8; 4], _) = scan.split_first_chunk().unwrap();
-    let remote_netmask = u32::from_be_bytes(remote_netmask);
-    if netmask != remote_netmask {
+    };
+    // highest version known by both
+    let version = max_version.min(PROTOCOL_VERSION);
+    if version < min_version || version < MIN_PROTOCOL_VERSION {
+        // let the client know why it is refused
+        channel.send(&Frame::HandshakeStatus {
+            status: STATUS_UNSUPPORTED_VERSION,
+        })?;
         bail!(
-            "HANDSHAKE error, netmask: {} instead of {}",
-            remote_netmask,
-            netmask
+            "HANDSHAKE error, no common protocol version: client {}-{}, server {}-{}",
+            min_version,
+            max_version,
+            MIN_PROTOCOL_VERSION,
+            PROTOCOL_VERSION
         );
     }
-    if !((local_addr & netmask == remote_addr & netmask) && (local_addr != remote_addr)) {
+    if !((capabilities ^ local.capabilities()) & INTERFACE).is_empty() {
+        channel.send(&Frame::HandshakeStatus {
+            status: STATUS_INTERFACE_MISMATCH,
+        })?;
         bail!(
-            "HANDSHAKE error, address: local {:#08x} remote {:#08x}",
-            local_addr,
-            remote_addr
+            "HANDSHAKE error, {} client, {} server",
+            interface_of(capabilities),
+            interface_of(local.capabilities())
         );
     }
-    Ok(())
+    let capabilities = capabilities & local.capabilities();
+    let negotiated = Negotiated {
+        version,
+        capabilities,
+        mtu: agree_on_mtu(local.mtu, capabilities, remote_mtu)?,
+    };
+    if remote_addrs.is_empty() {
+        let Some(pool) = pool else {
+            bail!("HANDSHAKE error, client asked for an address, no pool configured");
+        };
+        let lease = pool.assign(&[], identity)?;
+        let assigned = lease.addrs().to_vec();
+        return Ok((negotiated, lease, assigned));
+    }
+    check_addresses(&local.addrs, &remote_addrs)?;
+    let lease = match pool {
+        // addresses chosen inside the pool must not be assigned to others
+        Some(pool) => pool.assign(&remote_addrs, identity)?,
+        None => Lease::unpooled(&remote_addrs),
+    };
+    Ok((negotiated, lease, Vec::new()))
 }
 
-pub fn handler_client_handshake(
-    stream: &mut TcpStream,
-    ifaddr: &IpAddr,
-    netmask: u8,
-) -> Result<()> {
-    let IpAddr::V4(ifaddr) = ifaddr else {
-        bail!("Cannot accept IPv6");
-    };
-    // https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.try_clone
-    let ostream = stream.try_clone()?;
-    // https://doc.rust-lang.org/std/io/struct.BufWriter.html#method.with_capacity
-    let mut ostream = BufWriter::with_capacity(64, ostream);
-    // read stream
-    let istream = stream.try_clone()?;
-    let mut istream = BufReader::with_capacity(64, istream);
-
-    // classic netmask
-    let netmask = 0xFFFF_FFFFu32.wrapping_shl(32 - netmask as u32);
-    // local addr
-    let local_addr: u32 = u32::from_be_bytes(ifaddr.octets());
-    // 1. send intial packet: 16 bytes
-    send_initial_packet(&mut ostream, netmask, local_addr)?;
+// Return the interface configuration: the `local` addresses, or the
+// addresses assigned by the server if none, and the configuration pushed by
+// the server
+pub fn handler_client_handshake<S: Stream>(
+    channel: &mut Channel<S>,
+    local: &Endpoint,
+    noise: Option<&NoiseKeys>,
+    psk: Option<&Psk>,
+) -> Result<(Negotiated, ClientSetup)> {
+    // 0. key exchange
+    if let Some(keys) = noise {
+        noise::client_handshake(channel, keys)?;
+    }
+    // 0. authentication
+    if let Some(psk) = psk {
+        psk::client_auth(channel, psk)?;
+    }
+
+    // 1. send intial packet
+    send_initial_packet(channel, local)?;
     // 3. check server response
-    check_server_response(&mut istream, netmask, local_addr)?;
+    let ans = check_server_response(channel, local)?;
     // 4. send ok to server
-    send_ok_to_server(&mut ostream)?;
+    send_ok_to_server(channel)?;
     // SUCCESS
-    Ok(())
+    Ok(ans)
 }
 
-fn send_ok_to_server(ostream: &mut BufWriter<TcpStream>) -> Result<(), anyhow::Error> {
-    ostream.write_all(&3_u32.to_be_bytes())?;
-    ostream.write_all(&0_u32.to_be_bytes())?;
-    ostream.flush()?;
-    Ok(())
+fn send_ok_to_server<S: Read + Write>(channel: &mut Channel<S>) -> Result<(), anyhow::Error> {
+    channel.send(&Frame::HandshakeStatus { status: STATUS_OK })
 }
 
-fn check_server_response(
-    istream: &mut BufReader<TcpStream>,
-    netmask: u32,
-    local_addr: u32,
-) -> Result<(), anyhow::Error> {
-    let mut packet: [u8; 8] = [0; 8];
-    istream.read_exact(&mut packet)?;
-    let scan = packet.as_slice();
-    let (&pktid, scan): (&[u8; 4], _) = scan.split_first_chunk().unwrap();
-    let pktid = u32::from_be_bytes(pktid);
-    if pktid != 2 {
-        bail!("HANDSHAKE error, pktid: {} instead of {}", pktid, 2);
-    }
-    let (&remote_addr, _): (&[u8; 4], _) = scan.split_first_chunk().unwrap();
-    let remote_addr = u32::from_be_bytes(remote_addr);
-    if !((local_addr & netmask == remote_addr & netmask) && (local_addr != remote_addr)) {
+fn check_server_response<S: Read + Write>(
+    channel: &mut Channel<S>,
+    local: &Endpoint,
+) -> Result<(Negotiated, ClientSetup), anyhow::Error> {
+    let frame = channel.recv()?;
+    if let Frame::HandshakeStatus {
+        status: STATUS_UNSUPPORTED_VERSION,
+    } = frame
+    {
         bail!(
-            "HANDSHAKE error, address: local {:#08x} remote {:#08x}",
-            local_addr,
-            remote_addr
+            "HANDSHAKE error, server supports none of protocol versions {}-{}",
+            MIN_PROTOCOL_VERSION,
+            PROTOCOL_VERSION
         );
-    } else {
-        println!(
-            "Server interface address: {}",
-            IpAddr::from(remote_addr.to_be_bytes())
+    }
+    if let Frame::HandshakeStatus {
+        status: STATUS_INTERFACE_MISMATCH,
+    } = frame
+    {
+        bail!(
+            "HANDSHAKE error, server interface is not {}",
+            interface_of(local.capabilities())
         );
     }
-    Ok(())
+    let Frame::HelloReply {
+        version,
+        capabilities,
+        addrs: remote_addrs,
+        assigned,
+        routes,
+        dns,
+        domains,
+        mtu: remote_mtu,
+    } = frame
+    else {
+        bail!(
+            "HANDSHAKE error, frame: {} instead of {}",
+            frame.name(),
+            "HelloReply"
+        );
+    };
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+        bail!(
+            "HANDSHAKE error, server chose protocol version {} instead of {}-{}",
+            version,
+            MIN_PROTOCOL_VERSION,
+            PROTOCOL_VERSION
+        );
+    }
+    // servers not knowing TAP interfaces or offload do not refuse clients
+    // using them
+    if !((capabilities ^ local.capabilities()) & INTERFACE).is_empty() {
+        bail!(
+            "HANDSHAKE error, {} server, {} client",
+            interface_of(capabilities),
+            interface_of(local.capabilities())
+        );
+    }
+    let local_addrs = match (local.addrs.is_empty(), assigned.is_empty()) {
+        (false, true) => local.addrs.clone(),
+        (true, false) => {
+            for local in &assigned {
+                println!("Assigned interface address: {}", local);
+            }
+            assigned
+        }
+        (true, true) => bail!("HANDSHAKE error, server assigned no address"),
+        (false, false) => bail!("HANDSHAKE error, server assigned addresses not asked for"),
+    };
+    check_addresses(&local_addrs, &remote_addrs)?;
+    for remote in &remote_addrs {
+        println!("Server interface address: {}", remote.addr);
+    }
+    if let Some(route) = routes.iter().find(|route| route.network() != **route) {
+        bail!("HANDSHAKE error, route: {} is not a network", route);
+    }
+    let capabilities = capabilities & local.capabilities();
+    let negotiated = Negotiated {
+        version,
+        capabilities,
+        mtu: agree_on_mtu(local.mtu, capabilities, remote_mtu)?,
+    };
+    let setup = ClientSetup {
+        addrs: local_addrs,
+        server_addrs: remote_addrs,
+        pushed: Pushed {
+            routes,
+            dns: DnsConfig {
+                servers: dns,
+                domains,
+            },
+        },
+    };
+    Ok((negotiated, setup))
 }
 
-fn send_initial_packet(
-    ostream: &mut BufWriter<TcpStream>,
-    netmask: u32,
-    local_addr: u32,
+fn send_initial_packet<S: Read + Write>(
+    channel: &mut Channel<S>,
+    local: &Endpoint,
 ) -> Result<(), anyhow::Error> {
-    ostream.write_all(&MAGIC.to_be_bytes())?;
-    ostream.write_all(&1_u32.to_be_bytes())?;
-    ostream.write_all(&local_addr.to_be_bytes())?;
-    ostream.write_all(&netmask.to_be_bytes())?;
-    ostream.flush()?;
-    Ok(())
+    channel.send(&Frame::Hello {
+        min_version: MIN_PROTOCOL_VERSION,
+        max_version: PROTOCOL_VERSION,
+        capabilities: local.capabilities(),
+        addrs: local.addrs.clone(),
+        mtu: Some(local.mtu),
+    })
 }
@@ -0,0 +1,202 @@
+// Persistent leases: the addresses last assigned to every known client
+//
+// Clients are identified by a key proven during the handshake, their Noise
+// static key or the fingerprint of their TLS certificate, so a client gets
+// the same addresses back when it reconnects. Leases of clients not seen for
+// longer than the expiry are forgotten.
+//
+// The lease file holds one lease per line:
+//
+//      IDENTITY ADDR[,ADDR] LAST_SEEN
+//
+// where IDENTITY is "noise:" or "tls:" followed by 64 hex digits and
+// LAST_SEEN is in seconds since the Unix epoch. Empty lines and lines
+// starting with '#' are ignored.
+
+use crate::noise::{KEY_LEN, parse_key};
+use crate::tls::{Fingerprint, parse_fingerprint};
+use anyhow::{Context, Result, bail};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// Long-term identity of a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClientId {
+    // Noise static public key
+    Noise([u8; KEY_LEN]),
+    // SHA-256 fingerprint of the TLS client certificate
+    Tls(Fingerprint),
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientId::Noise(key) => write!(f, "noise:{}", hex(key)),
+            ClientId::Tls(fingerprint) => write!(f, "tls:{}", hex(fingerprint)),
+        }
+    }
+}
+
+impl FromStr for ClientId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some(("noise", key)) => Ok(ClientId::Noise(parse_key(key)?)),
+            Some(("tls", fingerprint)) => Ok(ClientId::Tls(parse_fingerprint(fingerprint)?)),
+            _ => bail!("Unknown client identity {}", s),
+        }
+    }
+}
+
+/// Addresses of a client and when it was last seen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredLease {
+    pub addrs: Vec<IpAddr>,
+    // seconds since the Unix epoch
+    pub last_seen: u64,
+}
+
+impl fmt::Display for StoredLease {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addrs: Vec<String> = self.addrs.iter().map(|addr| addr.to_string()).collect();
+        write!(f, "{} {}", addrs.join(","), self.last_seen)
+    }
+}
+
+/// Current time as stored in leases
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[derive(Debug)]
+pub struct LeaseFile {
+    path: PathBuf,
+    // None keeps leases forever
+    expiry: Option<Duration>,
+    leases: HashMap<ClientId, StoredLease>,
+}
+
+impl LeaseFile {
+    /// Load the leases stored in `path`, none if it does not exist yet
+    pub fn load(path: PathBuf, expiry: Option<Duration>) -> Result<Self> {
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err).with_context(|| format!("Cannot read {}", path.display())),
+        };
+        let leases = text
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(parse_lease)
+            .collect::<Result<_>>()
+            .with_context(|| format!("Invalid lease in {}", path.display()))?;
+        Ok(LeaseFile {
+            path,
+            expiry,
+            leases,
+        })
+    }
+
+    /// Write the leases back, replacing the file at once
+    pub fn save(&self) -> Result<()> {
+        let mut text = String::new();
+        for (id, lease) in &self.leases {
+            text += &format!("{} {}\n", id, lease);
+        }
+        write_file(&self.path, &text)
+    }
+
+    pub fn get(&self, id: &ClientId) -> Option<&StoredLease> {
+        self.leases.get(id)
+    }
+
+    /// True if `addr` is leased to a client other than `id`
+    pub fn leased_to_other(&self, addr: &IpAddr, id: Option<&ClientId>) -> bool {
+        self.leases
+            .iter()
+            .any(|(owner, lease)| Some(owner) != id && lease.addrs.contains(addr))
+    }
+
+    pub fn record(&mut self, id: ClientId, addrs: Vec<IpAddr>, now: u64) {
+        self.leases.insert(
+            id,
+            StoredLease {
+                addrs,
+                last_seen: now,
+            },
+        );
+    }
+
+    /// Update the last time `id` was seen, return false if it has no lease
+    pub fn touch(&mut self, id: &ClientId, now: u64) -> bool {
+        self.leases
+            .get_mut(id)
+            .map(|lease| lease.last_seen = now)
+            .is_some()
+    }
+
+    /// Forget leases not seen for longer than the expiry, except the ones
+    /// of connected clients (addresses in `in_use`)
+    ///
+    /// Return true if any lease expired.
+    pub fn expire(&mut self, now: u64, in_use: &HashSet<IpAddr>) -> bool {
+        let Some(expiry) = self.expiry else {
+            return false;
+        };
+        let count = self.leases.len();
+        self.leases.retain(|_, lease| {
+            now.saturating_sub(lease.last_seen) <= expiry.as_secs()
+                || lease.addrs.iter().any(|addr| in_use.contains(addr))
+        });
+        self.leases.len() != count
+    }
+
+    /// Every lease, sorted by identity
+    pub fn leases(&self) -> Vec<(ClientId, StoredLease)> {
+        let mut leases: Vec<_> = self
+            .leases
+            .iter()
+            .map(|(id, lease)| (*id, lease.clone()))
+            .collect();
+        leases.sort_by_key(|(id, _)| id.to_string());
+        leases
+    }
+}
+
+/// Replace the content of `path` at once with `text`, through a temporary
+/// file named after the whole file name, so files differing only by their
+/// extension do not share it
+pub fn write_file(path: &Path, text: &str) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, text)
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .with_context(|| format!("Cannot write {}", path.display()))
+}
+
+fn parse_lease(line: &str) -> Result<(ClientId, StoredLease)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [id, addrs, last_seen] = fields[..] else {
+        bail!("Expected IDENTITY ADDR[,ADDR] LAST_SEEN: {}", line);
+    };
+    let addrs = addrs
+        .split(',')
+        .map(|addr| Ok(addr.parse()?))
+        .collect::<Result<_>>()?;
+    let lease = StoredLease {
+        addrs,
+        last_seen: last_seen.parse()?,
+    };
+    Ok((id.parse()?, lease))
+}
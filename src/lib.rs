@@ -1,19 +1,43 @@
+pub mod channel;
 pub mod client;
+pub mod dns;
 pub mod flows;
 pub mod handshake;
+pub mod leases;
+pub mod netlink;
+pub mod noise;
 pub mod parsing;
+pub mod pool;
+pub mod protocol;
+pub mod psk;
+pub mod routing;
+pub mod sequence;
 pub mod server;
 pub mod signals;
+pub mod tls;
 pub mod tunif;
 use anyhow::Result;
 
 pub fn run(args: parsing::Args) -> Result<()> {
-    let ifname = args.interface.ifname;
-    let ifaddr = args.interface.ifaddr;
-    let netmask = args.interface.netmask;
+    let interface = args.interface;
+    let flow = args.flow;
+    let security = args.security;
+    let handshake_timeout = args.handshake_timeout;
     // different behaviour in case of client or server
     match args.mode {
-        parsing::Mode::Client { remote } => client::execute_client(ifname, ifaddr, netmask, remote),
-        parsing::Mode::Server { local } => server::execute_server(ifname, ifaddr, netmask, local),
+        parsing::Mode::Client(config) => {
+            client::execute_client(interface, config, flow, security, handshake_timeout)
+        }
+        parsing::Mode::Server(config) => {
+            server::execute_server(interface, config, flow, security, handshake_timeout)
+        }
+        parsing::Mode::MakeTun(owner) => tunif::make_persistent(
+            &interface.ifname,
+            &interface.addrs,
+            &interface.config,
+            owner.user,
+            owner.group,
+        ),
+        parsing::Mode::DeleteTun => tunif::delete_persistent(&interface.ifname),
     }
 }
@@ -0,0 +1,626 @@
+// Minimal rtnetlink client, enough to configure an interface, route
+// networks through it and find out how a host is reached
+//
+// Messages are built by hand: the netlink header, the fixed part of the
+// request (struct ifinfomsg, ifaddrmsg or rtmsg) then attributes encoded as
+// length, type and value, every part aligned on 4 bytes. All integers are in
+// host byte order. Every request asks for an acknowledgement, an NLMSG_ERROR
+// message carrying 0 on success or a negated errno. Dumps end with an
+// NLMSG_DONE message instead.
+
+use crate::tunif::{IfAddr, MacAddr};
+use anyhow::{Result, bail};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+// struct nlmsghdr
+const NLMSG_HDRLEN: usize = 16;
+// struct ifinfomsg
+const IFINFOMSG_LEN: usize = 16;
+// struct ifaddrmsg
+const IFADDRMSG_LEN: usize = 8;
+// struct rtmsg
+const RTMSG_LEN: usize = 12;
+const RECV_BUFFER_LEN: usize = 8192;
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn push_attr(msg: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    msg.extend(((4 + data.len()) as u16).to_ne_bytes());
+    msg.extend(kind.to_ne_bytes());
+    msg.extend(data);
+    msg.resize(align(msg.len()), 0);
+}
+
+// attributes of a message part as type and value, nested attributes
+// being parsed the same way from the value
+fn parse_attrs(mut data: &[u8]) -> io::Result<Vec<(u16, &[u8])>> {
+    let mut attrs = Vec::new();
+    while let Some((&header, rest)) = data.split_first_chunk::<4>() {
+        let len = u16::from_ne_bytes([header[0], header[1]]) as usize;
+        let kind = u16::from_ne_bytes([header[2], header[3]]) & libc::NLA_TYPE_MASK as u16;
+        if len < 4 || len - 4 > rest.len() {
+            return Err(invalid("truncated netlink attribute"));
+        }
+        attrs.push((kind, &rest[..len - 4]));
+        data = &data[align(len).min(data.len())..];
+    }
+    Ok(attrs)
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what)
+}
+
+/// Request made to the kernel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    GetLink(String),
+    DelLink(u32),
+    GetAddresses(u32),
+    SetLinkUp { ifindex: u32, up: bool },
+    SetMtu { ifindex: u32, mtu: u32 },
+    SetMac { ifindex: u32, mac: MacAddr },
+    AddAddress { ifindex: u32, addr: IfAddr },
+    DelAddress { ifindex: u32, addr: IfAddr },
+    AddRoute(Route),
+    DelRoute(Route),
+    GetRoute(IpAddr),
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::GetLink(name) => write!(f, "looking up interface {}", name),
+            Operation::DelLink(ifindex) => write!(f, "deleting interface {}", ifindex),
+            Operation::GetAddresses(ifindex) => {
+                write!(f, "listing addresses of interface {}", ifindex)
+            }
+            Operation::SetLinkUp { ifindex, up } => {
+                let state = if *up { "up" } else { "down" };
+                write!(f, "setting interface {} {}", ifindex, state)
+            }
+            Operation::SetMtu { ifindex, mtu } => {
+                write!(f, "setting MTU of interface {} to {}", ifindex, mtu)
+            }
+            Operation::SetMac { ifindex, mac } => {
+                write!(f, "setting MAC address of interface {} to {}", ifindex, mac)
+            }
+            Operation::AddAddress { ifindex, addr } => {
+                write!(f, "adding address {} to interface {}", addr, ifindex)
+            }
+            Operation::DelAddress { ifindex, addr } => {
+                write!(f, "removing address {} from interface {}", addr, ifindex)
+            }
+            Operation::AddRoute(route) => write!(f, "adding route {}", route.dst),
+            Operation::DelRoute(route) => write!(f, "removing route {}", route.dst),
+            Operation::GetRoute(dst) => write!(f, "looking up route to {}", dst),
+        }
+    }
+}
+
+/// Failed request: what was asked and why the kernel refused it
+#[derive(Debug)]
+pub struct NetlinkError {
+    pub operation: Operation,
+    pub source: io::Error,
+}
+
+impl NetlinkError {
+    pub fn kind(&self) -> io::ErrorKind {
+        self.source.kind()
+    }
+
+    /// errno returned by the kernel, None if the answer was not understood
+    pub fn raw_os_error(&self) -> Option<i32> {
+        self.source.raw_os_error()
+    }
+}
+
+impl fmt::Display for NetlinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Error {}: {}", self.operation, self.source)
+    }
+}
+
+impl std::error::Error for NetlinkError {}
+
+pub type NetlinkResult<T> = std::result::Result<T, NetlinkError>;
+
+pub struct Netlink {
+    fd: OwnedFd,
+    // sequence number of the last request
+    seq: u32,
+}
+
+impl Netlink {
+    pub fn new() -> Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            let err = io::Error::last_os_error();
+            bail!("Error creating netlink socket: {}", err);
+        }
+        Ok(Netlink {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            seq: 0,
+        })
+    }
+
+    // send a request to the kernel, return the messages answering it until
+    // its acknowledgement
+    fn request(&mut self, kind: u16, flags: u16, payload: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        self.seq = self.seq.wrapping_add(1);
+        let flags = flags | (libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16;
+        let mut msg = Vec::with_capacity(NLMSG_HDRLEN + payload.len());
+        msg.extend(((NLMSG_HDRLEN + payload.len()) as u32).to_ne_bytes());
+        msg.extend(kind.to_ne_bytes());
+        msg.extend(flags.to_ne_bytes());
+        msg.extend(self.seq.to_ne_bytes());
+        // port id, filled in by the kernel
+        msg.extend(0_u32.to_ne_bytes());
+        msg.extend(payload);
+        // unconnected netlink sockets send to the kernel
+        let res = unsafe { libc::send(self.fd.as_raw_fd(), msg.as_ptr().cast(), msg.len(), 0) };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut answers = Vec::new();
+        let mut buffer = vec![0_u8; RECV_BUFFER_LEN];
+        loop {
+            let sz = unsafe {
+                libc::recv(
+                    self.fd.as_raw_fd(),
+                    buffer.as_mut_ptr().cast(),
+                    buffer.len(),
+                    0,
+                )
+            };
+            if sz < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut msgs = &buffer[..sz as usize];
+            while msgs.len() >= NLMSG_HDRLEN {
+                let len = u32::from_ne_bytes(*msgs.first_chunk().unwrap()) as usize;
+                let kind = u16::from_ne_bytes(*msgs[4..].first_chunk().unwrap());
+                let seq = u32::from_ne_bytes(*msgs[8..].first_chunk().unwrap());
+                if len < NLMSG_HDRLEN || len > msgs.len() {
+                    return Err(invalid("truncated netlink message"));
+                }
+                // answers to other requests are skipped
+                if seq == self.seq {
+                    if kind == libc::NLMSG_DONE as u16 {
+                        return Ok(answers);
+                    } else if kind != libc::NLMSG_ERROR as u16 {
+                        answers.push(msgs[NLMSG_HDRLEN..len].to_vec());
+                    } else {
+                        let Some(errno) = msgs[NLMSG_HDRLEN..len].first_chunk() else {
+                            return Err(invalid("truncated netlink acknowledgement"));
+                        };
+                        return match i32::from_ne_bytes(*errno) {
+                            0 => Ok(answers),
+                            errno => Err(io::Error::from_raw_os_error(-errno)),
+                        };
+                    }
+                }
+                msgs = &msgs[align(len).min(msgs.len())..];
+            }
+        }
+    }
+
+    // `request` on behalf of `operation`, named by the error if it fails
+    fn run(
+        &mut self,
+        operation: Operation,
+        kind: u16,
+        flags: u16,
+        payload: &[u8],
+    ) -> NetlinkResult<Vec<Vec<u8>>> {
+        self.request(kind, flags, payload)
+            .map_err(|source| NetlinkError { operation, source })
+    }
+
+    /// Interface called `name`
+    pub fn get_link(&mut self, name: &str) -> NetlinkResult<Link> {
+        let operation = Operation::GetLink(name.to_string());
+        let mut msg = link_message(0, 0, 0);
+        let mut ifname = name.as_bytes().to_vec();
+        ifname.push(0);
+        push_attr(&mut msg, libc::IFLA_IFNAME, &ifname);
+        let answers = self.run(operation.clone(), libc::RTM_GETLINK, 0, &msg)?;
+        let failed = |source| NetlinkError {
+            operation: operation.clone(),
+            source,
+        };
+        let Some(answer) = answers
+            .first()
+            .filter(|answer| answer.len() >= IFINFOMSG_LEN)
+        else {
+            return Err(failed(invalid("missing interface in netlink answer")));
+        };
+        let mut link = Link {
+            index: u32::from_ne_bytes(*answer[4..].first_chunk().unwrap()),
+            mtu: None,
+            kind: None,
+        };
+        for (kind, data) in parse_attrs(&answer[IFINFOMSG_LEN..]).map_err(failed)? {
+            match (kind, data.len()) {
+                (libc::IFLA_MTU, 4) => {
+                    link.mtu = Some(u32::from_ne_bytes(*data.first_chunk().unwrap()))
+                }
+                (libc::IFLA_LINKINFO, _) => {
+                    for (kind, data) in parse_attrs(data).map_err(failed)? {
+                        if kind == libc::IFLA_INFO_KIND {
+                            let name = data.strip_suffix(&[0]).unwrap_or(data);
+                            link.kind = Some(String::from_utf8_lossy(name).into_owned());
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(link)
+    }
+
+    /// Delete interface `ifindex`
+    pub fn del_link(&mut self, ifindex: u32) -> NetlinkResult<()> {
+        let msg = link_message(ifindex, 0, 0);
+        self.run(Operation::DelLink(ifindex), libc::RTM_DELLINK, 0, &msg)?;
+        Ok(())
+    }
+
+    /// Global addresses of interface `ifindex`, leaving out link-local ones
+    pub fn get_addresses(&mut self, ifindex: u32) -> NetlinkResult<Vec<IfAddr>> {
+        let operation = Operation::GetAddresses(ifindex);
+        // every address of every interface, the kernel does not filter
+        let msg = vec![libc::AF_UNSPEC as u8, 0, 0, 0, 0, 0, 0, 0];
+        let flags = libc::NLM_F_DUMP as u16;
+        let answers = self.run(operation.clone(), libc::RTM_GETADDR, flags, &msg)?;
+        let failed = |source| NetlinkError {
+            operation: operation.clone(),
+            source,
+        };
+        let mut addrs = Vec::new();
+        for answer in &answers {
+            if answer.len() < IFADDRMSG_LEN {
+                return Err(failed(invalid("truncated address in netlink answer")));
+            }
+            let index = u32::from_ne_bytes(*answer[4..].first_chunk().unwrap());
+            if index != ifindex || answer[3] != libc::RT_SCOPE_UNIVERSE {
+                continue;
+            }
+            // the local address, IPv6 only gives the address
+            let mut found = None;
+            for (kind, data) in parse_attrs(&answer[IFADDRMSG_LEN..]).map_err(failed)? {
+                let addr: IpAddr = match data.len() {
+                    4 => Ipv4Addr::from(*data.first_chunk::<4>().unwrap()).into(),
+                    16 => Ipv6Addr::from(*data.first_chunk::<16>().unwrap()).into(),
+                    _ => continue,
+                };
+                match kind {
+                    libc::IFA_LOCAL => found = Some(addr),
+                    libc::IFA_ADDRESS => found = found.or(Some(addr)),
+                    _ => {}
+                }
+            }
+            if let Some(addr) = found {
+                addrs.push(IfAddr {
+                    addr,
+                    netmask: answer[1],
+                });
+            }
+        }
+        Ok(addrs)
+    }
+
+    /// Bring interface `ifindex` up or down
+    pub fn set_link_up(&mut self, ifindex: u32, up: bool) -> NetlinkResult<()> {
+        let flags = if up { libc::IFF_UP as u32 } else { 0 };
+        let msg = link_message(ifindex, flags, libc::IFF_UP as u32);
+        let operation = Operation::SetLinkUp { ifindex, up };
+        self.run(operation, libc::RTM_NEWLINK, 0, &msg)?;
+        Ok(())
+    }
+
+    pub fn set_mtu(&mut self, ifindex: u32, mtu: u32) -> NetlinkResult<()> {
+        let mut msg = link_message(ifindex, 0, 0);
+        push_attr(&mut msg, libc::IFLA_MTU, &mtu.to_ne_bytes());
+        let operation = Operation::SetMtu { ifindex, mtu };
+        self.run(operation, libc::RTM_NEWLINK, 0, &msg)?;
+        Ok(())
+    }
+
+    /// Set the hardware address of Ethernet interface `ifindex`
+    pub fn set_mac(&mut self, ifindex: u32, mac: MacAddr) -> NetlinkResult<()> {
+        let mut msg = link_message(ifindex, 0, 0);
+        push_attr(&mut msg, libc::IFLA_ADDRESS, &mac.0);
+        let operation = Operation::SetMac { ifindex, mac };
+        self.run(operation, libc::RTM_NEWLINK, 0, &msg)?;
+        Ok(())
+    }
+
+    /// Assign `addr` to interface `ifindex`, with the route to its network
+    pub fn add_address(&mut self, ifindex: u32, addr: &IfAddr) -> NetlinkResult<()> {
+        let msg = address_message(ifindex, addr);
+        let flags = libc::NLM_F_CREATE | libc::NLM_F_REPLACE;
+        let operation = Operation::AddAddress {
+            ifindex,
+            addr: *addr,
+        };
+        self.run(operation, libc::RTM_NEWADDR, flags as u16, &msg)?;
+        Ok(())
+    }
+
+    pub fn del_address(&mut self, ifindex: u32, addr: &IfAddr) -> NetlinkResult<()> {
+        let msg = address_message(ifindex, addr);
+        let operation = Operation::DelAddress {
+            ifindex,
+            addr: *addr,
+        };
+        self.run(operation, libc::RTM_DELADDR, 0, &msg)?;
+        Ok(())
+    }
+
+    /// Install `route`, replacing any route to the same network if
+    /// `replace`, failing with `AlreadyExists` otherwise
+    pub fn add_route(&mut self, route: &Route, replace: bool) -> NetlinkResult<()> {
+        // IPv6 ignores the scope
+        let scope = match (route.dst.addr, route.gateway) {
+            (IpAddr::V4(_), None) => libc::RT_SCOPE_LINK,
+            _ => libc::RT_SCOPE_UNIVERSE,
+        };
+        let msg = route_message(route, libc::RTPROT_STATIC, scope, libc::RTN_UNICAST);
+        let flags = libc::NLM_F_CREATE
+            | if replace {
+                libc::NLM_F_REPLACE
+            } else {
+                libc::NLM_F_EXCL
+            };
+        self.run(
+            Operation::AddRoute(*route),
+            libc::RTM_NEWROUTE,
+            flags as u16,
+            &msg,
+        )?;
+        Ok(())
+    }
+
+    /// Remove `route`
+    pub fn del_route(&mut self, route: &Route) -> NetlinkResult<()> {
+        // any protocol, scope and type
+        let msg = route_message(route, 0, libc::RT_SCOPE_NOWHERE, 0);
+        self.run(Operation::DelRoute(*route), libc::RTM_DELROUTE, 0, &msg)?;
+        Ok(())
+    }
+
+    /// Route the kernel currently uses to reach `dst`
+    pub fn get_route(&mut self, dst: IpAddr) -> NetlinkResult<RouteLookup> {
+        let (family, addr, len) = match dst {
+            IpAddr::V4(addr) => (libc::AF_INET, addr.octets().to_vec(), 32),
+            IpAddr::V6(addr) => (libc::AF_INET6, addr.octets().to_vec(), 128),
+        };
+        let mut msg = vec![family as u8, len, 0, 0, 0, 0, 0, 0];
+        msg.extend(0_u32.to_ne_bytes());
+        push_attr(&mut msg, libc::RTA_DST, &addr);
+        let operation = Operation::GetRoute(dst);
+        let answers = self.run(operation.clone(), libc::RTM_GETROUTE, 0, &msg)?;
+        let failed = |source| NetlinkError {
+            operation: operation.clone(),
+            source,
+        };
+        let Some(answer) = answers.first().filter(|answer| answer.len() >= RTMSG_LEN) else {
+            return Err(failed(invalid("missing route in netlink answer")));
+        };
+        let mut lookup = RouteLookup {
+            kind: answer[7],
+            gateway: None,
+            ifindex: None,
+        };
+        // attributes follow the fixed part
+        for (kind, data) in parse_attrs(&answer[RTMSG_LEN..]).map_err(failed)? {
+            match (kind, data.len()) {
+                (libc::RTA_GATEWAY, 4) => {
+                    lookup.gateway = Some(Ipv4Addr::from(*data.first_chunk::<4>().unwrap()).into())
+                }
+                (libc::RTA_GATEWAY, 16) => {
+                    lookup.gateway = Some(Ipv6Addr::from(*data.first_chunk::<16>().unwrap()).into())
+                }
+                (libc::RTA_OIF, 4) => {
+                    lookup.ifindex = Some(u32::from_ne_bytes(*data.first_chunk().unwrap()))
+                }
+                _ => {}
+            }
+        }
+        Ok(lookup)
+    }
+}
+
+/// Interface as described by the kernel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub index: u32,
+    pub mtu: Option<u32>,
+    // driver: "tun" for TUN and TAP interfaces, None for loopback or
+    // physical interfaces
+    pub kind: Option<String>,
+}
+
+/// Route of the main table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route {
+    // destination network
+    pub dst: IfAddr,
+    // next hop, None for on-link destinations
+    pub gateway: Option<IpAddr>,
+    // output interface
+    pub ifindex: u32,
+}
+
+/// Outcome of a route lookup
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteLookup {
+    // RTN_UNICAST, RTN_LOCAL...
+    pub kind: u8,
+    pub gateway: Option<IpAddr>,
+    pub ifindex: Option<u32>,
+}
+
+// struct ifinfomsg changing the `change` bits of the flags of interface
+// `ifindex` to `flags`, index 0 to look an interface up by name
+fn link_message(ifindex: u32, flags: u32, change: u32) -> Vec<u8> {
+    // family, padding, device type
+    let mut msg = vec![libc::AF_UNSPEC as u8, 0, 0, 0];
+    msg.extend(ifindex.to_ne_bytes());
+    msg.extend(flags.to_ne_bytes());
+    msg.extend(change.to_ne_bytes());
+    msg
+}
+
+// struct ifaddrmsg, then local address and, for IPv4, broadcast address
+fn address_message(ifindex: u32, ifaddr: &IfAddr) -> Vec<u8> {
+    let (family, octets) = match ifaddr.addr {
+        IpAddr::V4(addr) => (libc::AF_INET, addr.octets().to_vec()),
+        IpAddr::V6(addr) => (libc::AF_INET6, addr.octets().to_vec()),
+    };
+    // flags, scope
+    let mut msg = vec![family as u8, ifaddr.netmask, 0, libc::RT_SCOPE_UNIVERSE];
+    msg.extend(ifindex.to_ne_bytes());
+    push_attr(&mut msg, libc::IFA_LOCAL, &octets);
+    // same as the local address unless point-to-point
+    push_attr(&mut msg, libc::IFA_ADDRESS, &octets);
+    if let IpAddr::V4(addr) = ifaddr.addr
+        && ifaddr.netmask < 31
+    {
+        let broadcast = Ipv4Addr::from(u32::from(addr) | u32::MAX >> ifaddr.netmask);
+        push_attr(&mut msg, libc::IFA_BROADCAST, &broadcast.octets());
+    }
+    msg
+}
+
+// struct rtmsg for the main table, then destination, next hop and output
+// interface
+fn route_message(route: &Route, protocol: u8, scope: u8, kind: u8) -> Vec<u8> {
+    let octets = |addr: IpAddr| match addr {
+        IpAddr::V4(addr) => addr.octets().to_vec(),
+        IpAddr::V6(addr) => addr.octets().to_vec(),
+    };
+    let family = match route.dst.addr {
+        IpAddr::V4(_) => libc::AF_INET,
+        IpAddr::V6(_) => libc::AF_INET6,
+    };
+    let mut msg = vec![
+        family as u8,
+        route.dst.netmask,
+        // source prefix length, TOS
+        0,
+        0,
+        libc::RT_TABLE_MAIN,
+        protocol,
+        scope,
+        kind,
+    ];
+    // flags
+    msg.extend(0_u32.to_ne_bytes());
+    push_attr(&mut msg, libc::RTA_DST, &octets(route.dst.addr));
+    if let Some(gateway) = route.gateway {
+        push_attr(&mut msg, libc::RTA_GATEWAY, &octets(gateway));
+    }
+    push_attr(&mut msg, libc::RTA_OIF, &route.ifindex.to_ne_bytes());
+    msg
+}
+
+/// Routes installed while the tunnel is up, removed when dropped
+pub struct Routes {
+    netlink: Netlink,
+    // in installation order
+    routes: Vec<Route>,
+}
+
+impl Routes {
+    pub fn new() -> Result<Self> {
+        Ok(Routes {
+            netlink: Netlink::new()?,
+            routes: Vec::new(),
+        })
+    }
+
+    /// Route every network of `networks` through interface `ifindex`
+    ///
+    /// Networks are reached through the gateway of their family in
+    /// `gateways`, if any, directly otherwise. Networks already routed are
+    /// left alone, their routes are not ours to replace or remove: return
+    /// them.
+    pub fn add_all(
+        &mut self,
+        ifindex: u32,
+        networks: &[IfAddr],
+        gateways: &[IpAddr],
+    ) -> Result<Vec<IfAddr>> {
+        let mut existing = Vec::new();
+        for dst in networks {
+            let route = Route {
+                dst: *dst,
+                gateway: gateways
+                    .iter()
+                    .find(|gateway| gateway.is_ipv4() == dst.addr.is_ipv4())
+                    .copied(),
+                ifindex,
+            };
+            match self.netlink.add_route(&route, false) {
+                Ok(()) => self.routes.push(route),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => existing.push(*dst),
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(existing)
+    }
+
+    /// Keep reaching `host` the way it is reached now, whatever routes are
+    /// added later
+    ///
+    /// Return the host route, None if `host` is local or already has one.
+    pub fn pin_host(&mut self, host: IpAddr) -> Result<Option<Route>> {
+        let lookup = self.netlink.get_route(host)?;
+        let Some(ifindex) = lookup.ifindex.filter(|_| lookup.kind == libc::RTN_UNICAST) else {
+            return Ok(None);
+        };
+        let route = Route {
+            dst: IfAddr {
+                addr: host,
+                netmask: if host.is_ipv4() { 32 } else { 128 },
+            },
+            gateway: lookup.gateway,
+            ifindex,
+        };
+        match self.netlink.add_route(&route, false) {
+            Ok(()) => {}
+            // not ours to remove
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        self.routes.push(route);
+        Ok(Some(route))
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+}
+
+impl Drop for Routes {
+    fn drop(&mut self) {
+        for route in self.routes.iter().rev() {
+            // already gone if the interface went down
+            let _ = self.netlink.del_route(route);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+// Noise key exchange securing the channel
+//
+// The IK pattern is used: the client (initiator) knows the static public key
+// of the server in advance and sends its own static key encrypted in the
+// first message, so the server can check it against the authorized ones.
+// One round trip is enough to authenticate both ends and derive the
+// ChaCha20-Poly1305 keys of the session.
+//
+// Keys are 32 bytes X25519 keys stored hex encoded in text files.
+
+use crate::channel::{Channel, MAX_SEALED_LEN};
+use crate::protocol::Frame;
+use anyhow::{Context, Result, bail};
+use std::io::{Read, Write};
+use std::path::Path;
+
+const PATTERN: &str = "Noise_IK_25519_ChaChaPoly_BLAKE2s";
+pub const KEY_LEN: usize = 32;
+
+pub struct NoiseKeys {
+    // local static private key
+    private: [u8; KEY_LEN],
+    // client: server public key, server: public keys of authorized clients
+    peers: Vec<[u8; KEY_LEN]>,
+}
+
+pub(crate) fn parse_key(text: &str) -> Result<[u8; KEY_LEN]> {
+    let text = text.trim();
+    if text.len() != 2 * KEY_LEN || !text.is_ascii() {
+        bail!("Key must be {} hex digits", 2 * KEY_LEN);
+    }
+    let mut key = [0; KEY_LEN];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16)?;
+    }
+    Ok(key)
+}
+
+impl NoiseKeys {
+    pub fn new(private: [u8; KEY_LEN], peers: Vec<[u8; KEY_LEN]>) -> Self {
+        NoiseKeys { private, peers }
+    }
+
+    /// Load local private key and peer public keys (one per line, empty
+    /// lines and lines starting with '#' are ignored)
+    pub fn load(private_key: &Path, peer_keys: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(private_key)
+            .with_context(|| format!("Cannot read {}", private_key.display()))?;
+        let private = parse_key(&text)
+            .with_context(|| format!("Invalid private key in {}", private_key.display()))?;
+        let text = std::fs::read_to_string(peer_keys)
+            .with_context(|| format!("Cannot read {}", peer_keys.display()))?;
+        let peers = text
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(parse_key)
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("Invalid public key in {}", peer_keys.display()))?;
+        if peers.is_empty() {
+            bail!("No public key found in {}", peer_keys.display());
+        }
+        Ok(NoiseKeys { private, peers })
+    }
+}
+
+fn recv_noise_message<S: Read + Write>(
+    channel: &mut Channel<S>,
+    hs: &mut snow::HandshakeState,
+) -> Result<()> {
+    let frame = channel.recv()?;
+    let Frame::Noise { message } = frame else {
+        bail!(
+            "HANDSHAKE error, frame: {} instead of {}",
+            frame.name(),
+            "Noise"
+        );
+    };
+    let mut payload = vec![0; MAX_SEALED_LEN];
+    hs.read_message(message, &mut payload)?;
+    Ok(())
+}
+
+fn send_noise_message<S: Read + Write>(
+    channel: &mut Channel<S>,
+    hs: &mut snow::HandshakeState,
+) -> Result<()> {
+    let mut message = vec![0; MAX_SEALED_LEN];
+    let sz = hs.write_message(&[], &mut message)?;
+    channel.send(&Frame::Noise {
+        message: &message[..sz],
+    })
+}
+
+/// Run the initiator side, then encrypt the channel
+pub fn client_handshake<S: Read + Write>(channel: &mut Channel<S>, keys: &NoiseKeys) -> Result<()> {
+    let Some(server_key) = keys.peers.first() else {
+        bail!("Server public key required");
+    };
+    let mut hs = snow::Builder::new(PATTERN.parse()?)
+        .local_private_key(&keys.private)
+        .remote_public_key(server_key)
+        .build_initiator()?;
+    // -> e, es, s, ss
+    send_noise_message(channel, &mut hs)?;
+    // <- e, ee, se
+    recv_noise_message(channel, &mut hs)?;
+    let handshake_hash = hs.get_handshake_hash().to_vec();
+    channel.set_cipher(hs.into_transport_mode()?, &handshake_hash);
+    Ok(())
+}
+
+/// Run the responder side, reject unknown clients, then encrypt the channel
+///
+/// Return the static public key of the client.
+pub fn server_handshake<S: Read + Write>(
+    channel: &mut Channel<S>,
+    keys: &NoiseKeys,
+) -> Result<[u8; KEY_LEN]> {
+    let mut hs = snow::Builder::new(PATTERN.parse()?)
+        .local_private_key(&keys.private)
+        .build_responder()?;
+    // -> e, es, s, ss
+    recv_noise_message(channel, &mut hs)?;
+    let Some(client_key) = hs.get_remote_static() else {
+        bail!("HANDSHAKE error, missing client static key");
+    };
+    let Some(client_key) = keys.peers.iter().find(|key| *key == client_key).copied() else {
+        bail!("HANDSHAKE error, client key not authorized");
+    };
+    // <- e, ee, se
+    send_noise_message(channel, &mut hs)?;
+    let handshake_hash = hs.get_handshake_hash().to_vec();
+    channel.set_cipher(hs.into_transport_mode()?, &handshake_hash);
+    Ok(client_key)
+}
@@ -1,94 +1,528 @@
 // https://docs.rs/crate/argparse/0.2.2
 // https://docs.rs/clap/latest/clap/
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
-use anyhow::Result;
+use crate::dns::{DnsConfig, MAX_DNS_DOMAINS, MAX_DNS_SERVERS, RESOLV_CONF, valid_domain};
+use crate::flows::FlowConfig;
+use crate::handshake::{MAX_ROUTES, Pushed};
+use crate::noise::NoiseKeys;
+use crate::psk::Psk;
+use crate::tls::{TlsConfig, TlsOptions, parse_fingerprint};
+use crate::tunif::{DEFAULT_MTU, IfAddr, IfConfig, IfKind, MAX_MTU, MAX_QUEUES, MIN_MTU, MacAddr};
+use anyhow::{Result, bail};
+use nix::unistd::{Group, User};
 use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 
 const DEFAULT_IFNAME: &str = "tun0";
+const DEFAULT_TAP_IFNAME: &str = "tap0";
 
 // properties of virtual interface
 pub struct Interface {
     pub ifname: String,
-    pub ifaddr: IpAddr,
-    pub netmask: u8,
+    // at most one address per family, none for a client asking the server
+    pub addrs: Vec<IfAddr>,
+    pub config: IfConfig,
 }
 
 pub enum Mode {
     // when connecting to remote need both ip and port
-    Client {
-        // TCP related data
-        remote: std::net::SocketAddr,
-    },
+    Client(ClientConfig),
     // when acting as server require address and port to
     // bind to for incoming connections
-    Server {
-        // TCP related data
-        local: std::net::SocketAddr,
-    },
+    Server(ServerConfig),
+    // create a persistent interface for later unprivileged runs
+    MakeTun(TunOwner),
+    // delete a persistent interface
+    DeleteTun,
+}
+
+// users allowed to attach to a persistent interface, besides privileged ones
+pub struct TunOwner {
+    pub user: Option<u32>,
+    pub group: Option<u32>,
+}
+
+pub struct ClientConfig {
+    // TCP related data
+    pub remote: std::net::SocketAddr,
+    // resolver configuration the DNS settings pushed by the server are
+    // written to, None ignores them
+    pub resolv_conf: Option<PathBuf>,
+    // send all traffic through the tunnel
+    pub full_tunnel: bool,
+}
+
+pub struct ServerConfig {
+    // TCP related data
+    pub local: std::net::SocketAddr,
+    // forward packets between clients
+    pub client_to_client: bool,
+    // networks the addresses of clients asking for one are taken from, at
+    // most one per family
+    pub pools: Vec<IfAddr>,
+    // routes and DNS settings of clients
+    pub pushed: Pushed,
+    // file the addresses assigned to each client identity are kept in
+    pub lease_file: Option<PathBuf>,
+    // forget clients not seen for longer than this
+    pub lease_expiry: Option<Duration>,
+    // file describing connected clients and leases, rewritten on change
+    pub status_file: Option<PathBuf>,
+}
+
+// authentication and encryption of the connection
+#[derive(Default)]
+pub struct Security {
+    // encrypt the tunnel with Noise if keys are given
+    pub noise: Option<NoiseKeys>,
+    // run the tunnel over TLS
+    pub tls: Option<TlsConfig>,
+    // authenticate peers with a pre-shared key
+    pub psk: Option<Psk>,
 }
 
 // Program can execute both as client or server
 pub struct Args {
     pub interface: Interface,
     pub mode: Mode,
+    pub flow: FlowConfig,
+    pub security: Security,
+    // give up on handshakes lasting longer than this
+    pub handshake_timeout: Option<Duration>,
 }
 
 // clap seems better than argparse
-/// Simple TCP based L3 (TUN) point-to-point VPN server or client
+/// Simple TCP based L3 (TUN) or L2 (TAP) point-to-point VPN server or client
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true
+)]
 struct Opts {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     // properties of local (server) or remote (client) endpoint
     /// (server) IP to accept connections on (client) remote server IP
-    #[arg(long)]
-    host: String,
+    #[arg(long, required = true)]
+    host: Option<String>,
     /// (server) TCP port to listen for connection (client) remote server port
-    #[arg(short, long)]
-    port: u16,
+    #[arg(short, long, required = true)]
+    port: Option<u16>,
 
-    // properties describing virtual interface
-    /// virtual interface name
-    #[arg(long, default_value_t = String::from(DEFAULT_IFNAME))]
-    ifname: String,
-    /// IPv4 address of virtual interface
+    #[command(flatten)]
+    interface: InterfaceOpts,
+    /// (server) packet queues of the interface, each read by its own thread
+    #[arg(long, default_value_t = 1, requires = "server")]
+    queues: usize,
+    /// exchange TCP segments up to 64 KiB and packets with partial checksums with the kernel, both endpoints must use it
     #[arg(long)]
-    ifaddr: IpAddr,
-    /// netmask (1,32) of virtual interface address
-    #[arg(short, long)]
-    netmask: u8,
+    offload: bool,
+    /// use the persistent interface created by mktun as it is, without privileges: --tap, --ifaddr and --mtu must match it, routes and DNS settings are not installed
+    #[arg(long, requires = "ifaddr", conflicts_with_all = ["mac", "full_tunnel"])]
+    attach: bool,
 
     /// run as server (default: client)
     #[arg(short, long)]
     server: bool,
+    /// (server) let clients reach each other through the server
+    #[arg(long, requires = "server")]
+    client_to_client: bool,
+    /// (server) network as ADDR/NETMASK to assign addresses from to clients asking for one, repeat it for the other family
+    #[arg(long, requires = "server")]
+    pool: Vec<IfAddr>,
+    /// (server) network as ADDR/NETMASK clients route through the tunnel, repeatable (0.0.0.0/0 or ::/0 for all traffic, installed as two /1 halves)
+    #[arg(long, requires = "server")]
+    route: Vec<IfAddr>,
+    /// (server) DNS server pushed to clients, repeatable
+    #[arg(long, requires = "server")]
+    dns: Vec<IpAddr>,
+    /// (server) DNS search domain pushed to clients, repeatable
+    #[arg(long, requires = "server")]
+    dns_domain: Vec<String>,
+    /// (client) resolver configuration file the DNS settings pushed by the server are added to
+    #[arg(long, default_value = RESOLV_CONF, conflicts_with = "server")]
+    resolv_conf: PathBuf,
+    /// (client) leave the resolver configuration alone
+    #[arg(long, conflicts_with = "server")]
+    ignore_dns: bool,
+    /// (client) route all traffic through the tunnel, except the connection to the server
+    #[arg(long, conflicts_with = "server")]
+    full_tunnel: bool,
+    /// (server) file keeping the addresses assigned to clients authenticated by key or certificate, so they get them back on reconnection
+    #[arg(long, requires = "pool")]
+    lease_file: Option<PathBuf>,
+    /// (server) seconds after the last disconnection of a client its lease is forgotten (0 keeps it forever)
+    #[arg(long, default_value_t = 86400, requires = "lease_file")]
+    lease_expiry: u64,
+    /// (server) file listing connected clients and leases, rewritten whenever they change
+    #[arg(long, requires = "server")]
+    status_file: Option<PathBuf>,
+
+    // liveness of the remote endpoint
+    /// seconds between keepalive pings (0 disables them)
+    #[arg(long, default_value_t = 10)]
+    keepalive: u64,
+    /// seconds of silence after which the remote endpoint is considered dead (0 waits forever)
+    #[arg(long, default_value_t = 30)]
+    dead_peer_timeout: u64,
+    /// seconds a handshake may last before the connection is dropped (0 waits forever)
+    #[arg(long, default_value_t = 10)]
+    handshake_timeout: u64,
+    /// terminate the session if the remote endpoint replays a data packet
+    #[arg(long)]
+    reject_replay: bool,
+
+    // encryption
+    /// file containing the hex encoded X25519 private key, enables encryption
+    #[arg(long, requires = "peer_keys")]
+    private_key: Option<PathBuf>,
+    /// (server) file with public keys of authorized clients, one per line (client) file with the server public key
+    #[arg(long, requires = "private_key")]
+    peer_keys: Option<PathBuf>,
+
+    // authentication
+    /// file containing the pre-shared key both endpoints must know (default: RUST_TCP_VPN_PSK environment variable)
+    #[arg(long)]
+    psk_file: Option<PathBuf>,
+
+    // TLS transport
+    /// run the tunnel over TLS
+    #[arg(long)]
+    tls: bool,
+    /// PEM certificate chain (server: mandatory, client: for mutual authentication)
+    #[arg(long, requires = "tls")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of --tls-cert
+    #[arg(long, requires = "tls")]
+    tls_key: Option<PathBuf>,
+    /// PEM CA certificates the remote endpoint certificate must be signed by (server: requires client certificates)
+    #[arg(long, requires = "tls", conflicts_with = "tls_pin")]
+    tls_ca: Option<PathBuf>,
+    /// SHA-256 fingerprint of an accepted remote endpoint certificate, alternative to --tls-ca (server: requires client certificates)
+    #[arg(long, requires = "tls")]
+    tls_pin: Vec<String>,
+    /// (client) name expected in the server certificate (default: --host)
+    #[arg(long, requires = "tls")]
+    tls_server_name: Option<String>,
+}
+
+// properties describing virtual interface
+#[derive(clap::Args, Debug)]
+struct InterfaceOpts {
+    /// virtual interface name (default: tun0, tap0 with --tap)
+    #[arg(long)]
+    ifname: Option<String>,
+    /// IPv4 or IPv6 address of virtual interface as ADDR[/NETMASK], repeat it to add an address of the other family (client: default is asking the server)
+    #[arg(long)]
+    ifaddr: Vec<String>,
+    /// netmask (prefix length, up to 32 for IPv4 and 128 for IPv6) of virtual interface addresses given without one
+    #[arg(short, long)]
+    netmask: Option<u8>,
+    /// carry Ethernet frames over a TAP interface instead of IP packets, both endpoints must use it
+    #[arg(long)]
+    tap: bool,
+    /// MAC address of the TAP interface as XX:XX:XX:XX:XX:XX (default: random)
+    #[arg(long, requires = "tap")]
+    mac: Option<MacAddr>,
+    /// MTU of the interface, the tunnel uses the smallest of the MTUs of both endpoints
+    #[arg(long, default_value_t = DEFAULT_MTU)]
+    mtu: u32,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Create a persistent interface a user can run the VPN on without privileges, with --attach
+    Mktun {
+        #[command(flatten)]
+        interface: InterfaceOpts,
+        /// user allowed to attach to the interface, as name or UID
+        #[arg(long, required_unless_present = "group")]
+        user: Option<String>,
+        /// group whose members are allowed to attach to the interface, as name or GID
+        #[arg(long)]
+        group: Option<String>,
+        /// make a multi-queue interface, for servers attaching with --queues
+        #[arg(long)]
+        multi_queue: bool,
+    },
+    /// Delete a persistent interface
+    Rmtun {
+        /// interface name
+        #[arg(long)]
+        ifname: String,
+    },
+}
+
+// ADDR/NETMASK, or ADDR with the default netmask
+fn parse_ifaddr(text: &str, netmask: Option<u8>) -> Result<IfAddr> {
+    if text.contains('/') {
+        return text.parse();
+    }
+    let Some(netmask) = netmask else {
+        bail!("Missing netmask in {}, use ADDR/NETMASK or --netmask", text);
+    };
+    IfAddr::new(text.parse()?, netmask)
+}
+
+// properties of the interface described by `opts`
+fn parse_interface(
+    opts: InterfaceOpts,
+    queues: usize,
+    offload: bool,
+    attach: bool,
+) -> Result<Interface> {
+    let InterfaceOpts {
+        ifname,
+        ifaddr,
+        netmask,
+        tap,
+        mac,
+        mtu,
+    } = opts;
+    let addrs = ifaddr
+        .iter()
+        .map(|text| parse_ifaddr(text, netmask))
+        .collect::<Result<Vec<_>>>()?;
+    if mac.is_some_and(|mac| mac.is_multicast() || mac.0 == [0; 6]) {
+        bail!("MAC address {} is not an individual address", mac.unwrap());
+    }
+    let kind = if tap { IfKind::Tap } else { IfKind::Tun };
+    let ifname = ifname.unwrap_or_else(|| {
+        String::from(match kind {
+            IfKind::Tun => DEFAULT_IFNAME,
+            IfKind::Tap => DEFAULT_TAP_IFNAME,
+        })
+    });
+    if !(1..=MAX_QUEUES).contains(&queues) {
+        bail!("The interface has 1 to {} queues", MAX_QUEUES);
+    }
+    if !(MIN_MTU..=MAX_MTU).contains(&mtu) {
+        bail!("The MTU is between {} and {}", MIN_MTU, MAX_MTU);
+    }
+    Ok(Interface {
+        ifname,
+        addrs,
+        config: IfConfig {
+            kind,
+            mac,
+            queues,
+            offload,
+            mtu,
+            attach,
+        },
+    })
+}
+
+// user name or UID
+fn parse_user(text: &str) -> Result<u32> {
+    if let Ok(uid) = text.parse() {
+        return Ok(uid);
+    }
+    match User::from_name(text)? {
+        Some(user) => Ok(user.uid.as_raw()),
+        None => bail!("Unknown user {}", text),
+    }
+}
+
+// group name or GID
+fn parse_group(text: &str) -> Result<u32> {
+    if let Ok(gid) = text.parse() {
+        return Ok(gid);
+    }
+    match Group::from_name(text)? {
+        Some(group) => Ok(group.gid.as_raw()),
+        None => bail!("Unknown group {}", text),
+    }
+}
+
+// arguments of the subcommands, which only manage interfaces
+fn parse_command(command: Command) -> Result<Args> {
+    let (interface, mode) = match command {
+        Command::Mktun {
+            interface,
+            user,
+            group,
+            multi_queue,
+        } => {
+            // the queues are opened by the VPN, only the flag matters
+            let queues = if multi_queue { MAX_QUEUES } else { 1 };
+            let owner = TunOwner {
+                user: user.as_deref().map(parse_user).transpose()?,
+                group: group.as_deref().map(parse_group).transpose()?,
+            };
+            let interface = parse_interface(interface, queues, false, false)?;
+            (interface, Mode::MakeTun(owner))
+        }
+        Command::Rmtun { ifname } => {
+            let interface = Interface {
+                ifname,
+                addrs: Vec::new(),
+                config: IfConfig::default(),
+            };
+            (interface, Mode::DeleteTun)
+        }
+    };
+    Ok(Args {
+        interface,
+        mode,
+        flow: FlowConfig::default(),
+        security: Security::default(),
+        handshake_timeout: None,
+    })
+}
+
+// 0 disables the timer
+fn seconds(secs: u64) -> Option<Duration> {
+    (secs != 0).then(|| Duration::from_secs(secs))
 }
 
 pub fn parse_arg() -> Result<Args> {
-    let args = Opts::parse();
+    parse_opts(Opts::parse())
+}
+
+/// `parse_arg` on the command line `args`, starting with the program name,
+/// failing on usage errors rather than exiting
+pub fn parse_arg_from<I, T>(args: I) -> Result<Args>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    parse_opts(Opts::try_parse_from(args)?)
+}
+
+fn parse_opts(args: Opts) -> Result<Args> {
+    if let Some(command) = args.command {
+        return parse_command(command);
+    }
 
     let Opts {
+        command: _,
         host,
         port,
-        ifname,
-        ifaddr,
-        netmask,
+        interface,
+        queues,
+        offload,
+        attach,
         server,
+        client_to_client,
+        pool,
+        route,
+        dns,
+        dns_domain,
+        resolv_conf,
+        ignore_dns,
+        full_tunnel,
+        lease_file,
+        lease_expiry,
+        status_file,
+        keepalive,
+        dead_peer_timeout,
+        handshake_timeout,
+        reject_replay,
+        private_key,
+        peer_keys,
+        psk_file,
+        tls,
+        tls_cert,
+        tls_key,
+        tls_ca,
+        tls_pin,
+        tls_server_name,
     } = args;
+    // required unless running a subcommand
+    let (Some(host), Some(port)) = (host, port) else {
+        bail!("Missing --host or --port");
+    };
+    let interface = parse_interface(interface, queues, offload, attach)?;
+    if server && interface.addrs.is_empty() {
+        bail!("The server needs an interface address");
+    }
+    if let Some(route) = route.iter().find(|route| route.network() != **route) {
+        bail!("Route {} has host bits set, use {}", route, route.network());
+    }
+    if route.len() > MAX_ROUTES {
+        bail!("At most {} routes can be pushed", MAX_ROUTES);
+    }
+    if dns.len() > MAX_DNS_SERVERS || dns_domain.len() > MAX_DNS_DOMAINS {
+        bail!(
+            "At most {} DNS servers and {} domains can be pushed",
+            MAX_DNS_SERVERS,
+            MAX_DNS_DOMAINS
+        );
+    }
+    if let Some(domain) = dns_domain.iter().find(|domain| !valid_domain(domain)) {
+        bail!("Invalid DNS domain {}", domain);
+    }
+    let noise = match (private_key, peer_keys) {
+        (Some(private_key), Some(peer_keys)) => Some(NoiseKeys::load(&private_key, &peer_keys)?),
+        _ => None,
+    };
+    let psk = match psk_file {
+        Some(path) => Some(Psk::load(&path)?),
+        None => Psk::from_env()?,
+    };
+    let tls = if tls {
+        let opts = TlsOptions {
+            cert: tls_cert,
+            key: tls_key,
+            ca: tls_ca,
+            pins: tls_pin
+                .iter()
+                .map(|pin| parse_fingerprint(pin))
+                .collect::<Result<_>>()?,
+            server_name: tls_server_name,
+        };
+        Some(if server {
+            TlsConfig::server(&opts)?
+        } else {
+            TlsConfig::client(&opts, &host)?
+        })
+    } else {
+        None
+    };
     // https://doc.rust-lang.org/std/str/trait.FromStr.html#tymethod.from_str
     let host = IpAddr::from_str(&host)?;
     // IP address to be used in network connection
     let addr = SocketAddr::new(host, port);
     Ok(Args {
-        interface: Interface {
-            ifname,
-            ifaddr,
-            netmask,
-        },
+        interface,
         mode: if server {
-            Mode::Server { local: addr }
+            Mode::Server(ServerConfig {
+                local: addr,
+                client_to_client,
+                pools: pool,
+                pushed: Pushed {
+                    routes: route,
+                    dns: DnsConfig {
+                        servers: dns,
+                        domains: dns_domain,
+                    },
+                },
+                lease_file,
+                lease_expiry: seconds(lease_expiry),
+                status_file,
+            })
         } else {
-            Mode::Client { remote: addr }
+            Mode::Client(ClientConfig {
+                remote: addr,
+                resolv_conf: (!ignore_dns).then_some(resolv_conf),
+                full_tunnel,
+            })
+        },
+        flow: FlowConfig {
+            keepalive: seconds(keepalive),
+            dead_peer_timeout: seconds(dead_peer_timeout),
+            reject_replay,
+            ..FlowConfig::default()
         },
+        security: Security { noise, tls, psk },
+        handshake_timeout: seconds(handshake_timeout),
     })
 }
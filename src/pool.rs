@@ -0,0 +1,290 @@
+// Addresses assigned by the server to clients not choosing their own
+//
+// Every pool is a network inside the server interface subnet of the same
+// family. Addresses of connected clients, assigned or chosen by the client
+// inside a pool, are reserved until the client goes away, so no address is
+// ever given to two clients at once.
+//
+// With a lease file, clients with an identity get back the addresses they
+// had last time, which are not given to anyone else until the lease expires.
+
+use crate::leases::{self, ClientId, LeaseFile, StoredLease};
+use crate::tunif::IfAddr;
+use anyhow::{Result, bail};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+
+// addresses of a pool as integers, IPv4 ones widened
+#[derive(Debug)]
+struct Range {
+    network: IfAddr,
+    first: u128,
+    last: u128,
+    ipv4: bool,
+    // netmask of the server interface, given to clients
+    netmask: u8,
+}
+
+impl Range {
+    fn addr(&self, n: u128) -> IpAddr {
+        if self.ipv4 {
+            Ipv4Addr::from(n as u32).into()
+        } else {
+            Ipv6Addr::from(n).into()
+        }
+    }
+
+    fn contains(&self, addr: &IpAddr) -> bool {
+        let n = match addr {
+            IpAddr::V4(addr) if self.ipv4 => u32::from(*addr) as u128,
+            IpAddr::V6(addr) if !self.ipv4 => u128::from(*addr),
+            _ => return false,
+        };
+        (self.first..=self.last).contains(&n)
+    }
+}
+
+fn bits(addr: &IpAddr) -> (u128, u32) {
+    match addr {
+        IpAddr::V4(addr) => (u32::from(*addr) as u128, 32),
+        IpAddr::V6(addr) => (u128::from(*addr), 128),
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    // addresses of the server and of connected clients
+    in_use: HashSet<IpAddr>,
+    // None if leases are not persisted
+    leases: Option<LeaseFile>,
+}
+
+impl State {
+    // the file is only a cache of the assignments, serving clients goes on
+    // if it cannot be written
+    fn save_leases(&self) {
+        if let Some(Err(err)) = self.leases.as_ref().map(LeaseFile::save) {
+            eprintln!("Cannot save leases: {:#}", err);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AddressPool {
+    ranges: Vec<Range>,
+    state: Mutex<State>,
+}
+
+impl AddressPool {
+    /// Pool of the `pools` networks, at most one per family, for a server
+    /// with interface addresses `server_addrs`
+    ///
+    /// Every family of the server needs a pool, so that clients get an
+    /// address of each. Assignments are persisted to `leases`, if any.
+    pub fn new(
+        pools: &[IfAddr],
+        server_addrs: &[IfAddr],
+        leases: Option<LeaseFile>,
+    ) -> Result<Arc<Self>> {
+        let mut ranges: Vec<Range> = Vec::new();
+        for pool in pools {
+            let Some(server) = server_addrs
+                .iter()
+                .find(|server| server.addr.is_ipv4() == pool.addr.is_ipv4())
+            else {
+                bail!("Pool {} has no interface address of its family", pool);
+            };
+            if ranges.iter().any(|range| range.ipv4 == pool.addr.is_ipv4()) {
+                bail!("At most one pool per family is supported");
+            }
+            let inside =
+                pool.addr == server.addr || IfAddr::new(pool.addr, server.netmask)?.is_peer(server);
+            if pool.netmask < server.netmask || !inside {
+                bail!("Pool {} is not inside the subnet of {}", pool, server);
+            }
+            let (addr, width) = bits(&pool.addr);
+            let host_bits = width - pool.netmask as u32;
+            let hosts = u128::MAX.checked_shr(128 - host_bits).unwrap_or(0);
+            let (mut first, mut last) = (addr & !hosts, addr | hosts);
+            // network and broadcast addresses of IPv4 subnets are never
+            // assigned, neither is the IPv6 subnet-router anycast address
+            if host_bits >= 2 {
+                first += 1;
+                if pool.addr.is_ipv4() {
+                    last -= 1;
+                }
+            }
+            ranges.push(Range {
+                network: *pool,
+                first,
+                last,
+                ipv4: pool.addr.is_ipv4(),
+                netmask: server.netmask,
+            });
+        }
+        for server in server_addrs {
+            if !ranges
+                .iter()
+                .any(|range| range.ipv4 == server.addr.is_ipv4())
+            {
+                bail!("No pool for interface address {}", server);
+            }
+        }
+        let in_use = server_addrs.iter().map(|server| server.addr).collect();
+        Ok(Arc::new(AddressPool {
+            ranges,
+            state: Mutex::new(State { in_use, leases }),
+        }))
+    }
+
+    // addresses of the lease of `identity`, if it covers every pool and
+    // none of them is in use
+    fn leased(&self, state: &State, identity: Option<ClientId>) -> Option<Vec<IfAddr>> {
+        let lease = state.leases.as_ref()?.get(&identity?)?;
+        self.ranges
+            .iter()
+            .map(|range| {
+                let addr = lease.addrs.iter().find(|addr| range.contains(addr))?;
+                (!state.in_use.contains(addr)).then_some(IfAddr {
+                    addr: *addr,
+                    netmask: range.netmask,
+                })
+            })
+            .collect()
+    }
+
+    // one free address of each pool, not leased to another client
+    fn allocate(&self, state: &State, identity: Option<ClientId>) -> Result<Vec<IfAddr>> {
+        let mut addrs = Vec::new();
+        for range in &self.ranges {
+            let Some(addr) = (range.first..=range.last)
+                .map(|n| range.addr(n))
+                .find(|addr| {
+                    !state.in_use.contains(addr)
+                        && !state
+                            .leases
+                            .as_ref()
+                            .is_some_and(|leases| leases.leased_to_other(addr, identity.as_ref()))
+                })
+            else {
+                bail!("Address pool {} exhausted", range.network);
+            };
+            addrs.push(IfAddr {
+                addr,
+                netmask: range.netmask,
+            });
+        }
+        Ok(addrs)
+    }
+
+    /// Known clients and their last addresses, sorted by identity
+    pub fn leases(&self) -> Vec<(ClientId, StoredLease)> {
+        let state = self.state.lock().unwrap();
+        state.leases.as_ref().map_or(Vec::new(), LeaseFile::leases)
+    }
+
+    /// Reserve the addresses `requested` by a client, or assign it one
+    /// address of each pool if it requested none
+    ///
+    /// A client with an `identity` is assigned the addresses of its lease
+    /// if they are free, and its lease is updated. Fails if any address
+    /// inside a pool is already in use or leased to another client, or if
+    /// a pool is exhausted.
+    /// Requested addresses outside the pools are not tracked.
+    pub fn assign(
+        self: &Arc<Self>,
+        requested: &[IfAddr],
+        identity: Option<ClientId>,
+    ) -> Result<Lease> {
+        let mut state = self.state.lock().unwrap();
+        let now = leases::now();
+        let state = &mut *state;
+        if let Some(leases) = &mut state.leases
+            && leases.expire(now, &state.in_use)
+        {
+            state.save_leases();
+        }
+        let (addrs, reserved) = if requested.is_empty() {
+            let addrs = match self.leased(state, identity) {
+                Some(addrs) => addrs,
+                None => self.allocate(state, identity)?,
+            };
+            let reserved: Vec<IpAddr> = addrs.iter().map(|ifaddr| ifaddr.addr).collect();
+            if let (Some(leases), Some(id)) = (&mut state.leases, identity) {
+                leases.record(id, reserved.clone(), now);
+                state.save_leases();
+            }
+            (addrs, reserved)
+        } else {
+            let reserved: Vec<IpAddr> = requested
+                .iter()
+                .map(|ifaddr| ifaddr.addr)
+                .filter(|addr| self.ranges.iter().any(|range| range.contains(addr)))
+                .collect();
+            if let Some(addr) = reserved.iter().find(|addr| state.in_use.contains(addr)) {
+                bail!("Address {} already in use by another client", addr);
+            }
+            // kept for its client while it is away
+            if let Some(leases) = &state.leases
+                && let Some(addr) = reserved
+                    .iter()
+                    .find(|addr| leases.leased_to_other(addr, identity.as_ref()))
+            {
+                bail!("Address {} is leased to another client", addr);
+            }
+            (requested.to_vec(), reserved)
+        };
+        state.in_use.extend(&reserved);
+        Ok(Lease {
+            addrs,
+            identity,
+            pool: Some((self.clone(), reserved)),
+        })
+    }
+}
+
+/// Interface addresses of a connected client, addresses of the pool stay
+/// reserved until it is dropped
+#[derive(Debug)]
+pub struct Lease {
+    addrs: Vec<IfAddr>,
+    identity: Option<ClientId>,
+    pool: Option<(Arc<AddressPool>, Vec<IpAddr>)>,
+}
+
+impl Lease {
+    /// Addresses chosen by a client of a server without pools
+    pub fn unpooled(addrs: &[IfAddr]) -> Self {
+        Lease {
+            addrs: addrs.to_vec(),
+            identity: None,
+            pool: None,
+        }
+    }
+
+    pub fn addrs(&self) -> &[IfAddr] {
+        &self.addrs
+    }
+
+    pub fn identity(&self) -> Option<ClientId> {
+        self.identity
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        if let Some((pool, reserved)) = &self.pool {
+            let mut state = pool.state.lock().unwrap();
+            for addr in reserved {
+                state.in_use.remove(addr);
+            }
+            // the client was last seen now
+            if let (Some(leases), Some(id)) = (&mut state.leases, &self.identity)
+                && leases.touch(id, leases::now())
+            {
+                state.save_leases();
+            }
+        }
+    }
+}
@@ -0,0 +1,888 @@
+// Wire format of the VPN protocol
+//
+// Every frame starts with a fixed 8 bytes header followed by a body:
+//
+//      +------------+------------+-----------------+
+//      | type (u32) | len (u32)  | body (len bytes)|
+//      +------------+------------+-----------------+
+//
+// All integers are big endian. Since every frame carries its body length,
+// a receiver can always find the next frame boundary, so new frame types
+// can be added without breaking the framing of older ones.
+//
+// The handshake starts with the client announcing the range of protocol
+// versions and the optional features (capabilities) it supports in the
+// Hello frame, the server picks the highest common version and both ends
+// use the capabilities supported by both, except ETHERNET and OFFLOAD which
+// both must announce or neither, as they change the content of data frames:
+// TUN and TAP endpoints, or endpoints with and without offload, cannot be
+// mixed. Later versions may append fields to the Hello frame, the fields
+// known so far keep their position: the interface addresses are followed by
+// the MTU of the client interface (u32), if it announces the MTU capability.
+//
+// Interface address lists are encoded as the number of addresses (u32),
+// then every address as family (4 or 6, u32), prefix length (u32) and the
+// 4 or 16 bytes of the address.
+//
+// The HelloReply frame ends with the addresses assigned to a client that
+// asked the server for them (sending none in its Hello frame), then the
+// routes the client installs through the tunnel, encoded as an address list
+// of networks, the DNS servers, encoded as an address list of hosts, and
+// the DNS search domains: their number (u32), then every domain as its
+// length (u32) followed by its characters. Empty lists at the end are left
+// out, as older clients expect nothing after the server addresses; routes
+// and DNS settings are only sent to clients announcing the ROUTES and DNS
+// capabilities. Clients announcing the MTU capability get the MTU of the
+// server interface (u32) after the search domains, every list being encoded
+// then. Both ends use the smallest of the two MTUs.
+
+use crate::dns::valid_domain;
+use crate::tunif::{IfAddr, MAX_MTU, MIN_MTU};
+use bitflags::bitflags;
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// First field of the `Hello` frame, identifies the protocol
+pub const MAGIC: u32 = 0x12345678;
+/// Newest protocol version implemented
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest protocol version still accepted
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+/// `HandshakeStatus`: handshake completed
+pub const STATUS_OK: u32 = 0;
+/// `HandshakeStatus`: (server -> client) no protocol version in common
+pub const STATUS_UNSUPPORTED_VERSION: u32 = 1;
+/// `HandshakeStatus`: (server -> client) interfaces of the endpoints cannot
+/// be mixed (TUN and TAP, offload or not)
+pub const STATUS_INTERFACE_MISMATCH: u32 = 2;
+/// Size of the header preceding every frame body
+pub const HEADER_LEN: usize = 8;
+/// Maximum size of the inner packet carried by a single data frame
+pub const MAX_PAYLOAD_LEN: usize = 65535;
+/// Maximum size of a frame body accepted by default
+pub const MAX_BODY_LEN: usize = max_body_len(MAX_PAYLOAD_LEN);
+
+/// Size of the random nonces exchanged by the pre-shared key authentication
+pub const AUTH_NONCE_LEN: usize = 32;
+/// Size of the HMAC-SHA256 proofs exchanged by the pre-shared key authentication
+pub const AUTH_MAC_LEN: usize = 32;
+
+/// Size of the largest frame body carrying inner packets up to `max_payload`
+pub const fn max_body_len(max_payload: usize) -> usize {
+    // data frame: counter + payload
+    max_payload + 8
+}
+
+// frame types
+const DATA: u32 = 1;
+const EXIT: u32 = 2;
+const PING: u32 = 3;
+const PONG: u32 = 4;
+const HELLO: u32 = 0x10;
+const HELLO_REPLY: u32 = 0x11;
+const HANDSHAKE_STATUS: u32 = 0x12;
+const NOISE: u32 = 0x13;
+const AUTH_CHALLENGE: u32 = 0x14;
+const AUTH_RESPONSE: u32 = 0x15;
+const AUTH_PROOF: u32 = 0x16;
+const SEALED: u32 = 0x20;
+
+bitflags! {
+    /// Optional features announced in the handshake
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Capabilities: u32 {
+        // answers Ping frames with Pong frames
+        const KEEPALIVE = 1 << 0;
+        // installs the routes pushed by the server
+        const ROUTES = 1 << 1;
+        // applies the DNS settings pushed by the server
+        const DNS = 1 << 2;
+        // data frames carry Ethernet frames (TAP) instead of IP packets,
+        // both endpoints must announce it or neither
+        const ETHERNET = 1 << 3;
+        // data frames carry a virtio-net header before the packet, which
+        // may be up to 64 KiB, both endpoints must announce it or neither
+        const OFFLOAD = 1 << 4;
+        // exchanges interface MTUs in the handshake
+        const MTU = 1 << 5;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame<'a> {
+    // inner network packet and its sequence number
+    Data {
+        counter: u64,
+        payload: &'a [u8],
+    },
+    // terminate the VPN protocol, 0 is regular exit
+    Exit {
+        reason: u32,
+    },
+    // keepalive request, timestamp is opaque to the receiver
+    Ping {
+        timestamp: u64,
+    },
+    // keepalive reply, echoes the timestamp of the Ping
+    Pong {
+        timestamp: u64,
+    },
+    // 1. client -> server: supported versions and features, client
+    // interface properties
+    Hello {
+        min_version: u32,
+        max_version: u32,
+        capabilities: Capabilities,
+        addrs: Vec<IfAddr>,
+        mtu: Option<u32>,
+    },
+    // 3. server -> client: chosen version, server features, interface
+    // addresses, addresses assigned to the client, routes to install, DNS
+    // settings and interface MTU
+    HelloReply {
+        version: u32,
+        capabilities: Capabilities,
+        addrs: Vec<IfAddr>,
+        assigned: Vec<IfAddr>,
+        routes: Vec<IfAddr>,
+        dns: Vec<IpAddr>,
+        domains: Vec<String>,
+        mtu: Option<u32>,
+    },
+    // 4. client -> server: outcome of the handshake, 0 is success
+    // (server -> client: handshake refused, see STATUS_*)
+    HandshakeStatus {
+        status: u32,
+    },
+    // Noise handshake message
+    Noise {
+        message: &'a [u8],
+    },
+    // client -> server: pre-shared key authentication request
+    AuthChallenge {
+        nonce: &'a [u8; AUTH_NONCE_LEN],
+    },
+    // server -> client: server proof of the key, and its own challenge
+    AuthResponse {
+        nonce: &'a [u8; AUTH_NONCE_LEN],
+        mac: &'a [u8; AUTH_MAC_LEN],
+    },
+    // client -> server: client proof of the key
+    AuthProof {
+        mac: &'a [u8; AUTH_MAC_LEN],
+    },
+    // encrypted chunk of the frame stream, once the Noise handshake is over
+    Sealed {
+        ciphertext: &'a [u8],
+    },
+}
+
+impl Frame<'_> {
+    fn kind(&self) -> u32 {
+        match self {
+            Frame::Data { .. } => DATA,
+            Frame::Exit { .. } => EXIT,
+            Frame::Ping { .. } => PING,
+            Frame::Pong { .. } => PONG,
+            Frame::Hello { .. } => HELLO,
+            Frame::HelloReply { .. } => HELLO_REPLY,
+            Frame::HandshakeStatus { .. } => HANDSHAKE_STATUS,
+            Frame::Noise { .. } => NOISE,
+            Frame::AuthChallenge { .. } => AUTH_CHALLENGE,
+            Frame::AuthResponse { .. } => AUTH_RESPONSE,
+            Frame::AuthProof { .. } => AUTH_PROOF,
+            Frame::Sealed { .. } => SEALED,
+        }
+    }
+
+    fn body_len(&self) -> usize {
+        match self {
+            Frame::Data { payload, .. } => 8 + payload.len(),
+            Frame::Exit { .. } => 4,
+            Frame::Ping { .. } | Frame::Pong { .. } => 8,
+            Frame::Hello { addrs, mtu, .. } => 16 + addrs_len(addrs) + mtu.map_or(0, |_| 4),
+            Frame::HelloReply {
+                addrs,
+                assigned,
+                routes,
+                dns,
+                domains,
+                mtu,
+                ..
+            } => {
+                let trailing = [
+                    addrs_len(assigned),
+                    addrs_len(routes),
+                    addrs_len(&host_addrs(dns)),
+                    domains_len(domains),
+                ];
+                let count = trailing_lists(assigned, routes, dns, domains, *mtu);
+                8 + addrs_len(addrs)
+                    + trailing[..count].iter().sum::<usize>()
+                    + mtu.map_or(0, |_| 4)
+            }
+            Frame::HandshakeStatus { .. } => 4,
+            Frame::Noise { message } => message.len(),
+            Frame::AuthChallenge { .. } => AUTH_NONCE_LEN,
+            Frame::AuthResponse { .. } => AUTH_NONCE_LEN + AUTH_MAC_LEN,
+            Frame::AuthProof { .. } => AUTH_MAC_LEN,
+            Frame::Sealed { ciphertext } => ciphertext.len(),
+        }
+    }
+
+    /// Human readable name, used in error messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            Frame::Data { .. } => "Data",
+            Frame::Exit { .. } => "Exit",
+            Frame::Ping { .. } => "Ping",
+            Frame::Pong { .. } => "Pong",
+            Frame::Hello { .. } => "Hello",
+            Frame::HelloReply { .. } => "HelloReply",
+            Frame::HandshakeStatus { .. } => "HandshakeStatus",
+            Frame::Noise { .. } => "Noise",
+            Frame::AuthChallenge { .. } => "AuthChallenge",
+            Frame::AuthResponse { .. } => "AuthResponse",
+            Frame::AuthProof { .. } => "AuthProof",
+            Frame::Sealed { .. } => "Sealed",
+        }
+    }
+}
+
+/// Reason carried by the `Exit` frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    // regular termination
+    Normal,
+    // sender received a malformed or unexpected frame
+    ProtocolViolation,
+    // sender stopped receiving anything from the remote endpoint
+    PeerTimeout,
+    // sender received a replayed data frame
+    Replay,
+    // code not known by this implementation
+    Unknown(u32),
+}
+
+impl ExitReason {
+    pub fn code(self) -> u32 {
+        match self {
+            ExitReason::Normal => 0,
+            ExitReason::ProtocolViolation => 1,
+            ExitReason::PeerTimeout => 2,
+            ExitReason::Replay => 3,
+            ExitReason::Unknown(code) => code,
+        }
+    }
+
+    pub fn from_code(code: u32) -> Self {
+        match code {
+            0 => ExitReason::Normal,
+            1 => ExitReason::ProtocolViolation,
+            2 => ExitReason::PeerTimeout,
+            3 => ExitReason::Replay,
+            code => ExitReason::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitReason::Normal => write!(f, "normal exit"),
+            ExitReason::ProtocolViolation => write!(f, "protocol violation"),
+            ExitReason::PeerTimeout => write!(f, "peer timeout"),
+            ExitReason::Replay => write!(f, "replayed data frame"),
+            ExitReason::Unknown(code) => write!(f, "unknown exit reason code {}", code),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    // body length exceeds the accepted limit
+    TooLong { len: usize, max: usize },
+    // frame type not known by this implementation
+    UnknownType(u32),
+    // body length not valid for the given frame type
+    BadLength { kind: u32, len: usize },
+    // Hello frame not starting with MAGIC
+    BadMagic(u32),
+    // field with out of range value
+    InvalidField(&'static str),
+    // sealed frame not authenticated, or cleartext frame in sealed channel
+    Unauthenticated,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::TooLong { len, max } => {
+                write!(f, "frame body of {} bytes exceeds limit of {}", len, max)
+            }
+            DecodeError::UnknownType(kind) => write!(f, "unknown frame type: {}", kind),
+            DecodeError::BadLength { kind, len } => {
+                write!(f, "invalid body length {} for frame type {}", len, kind)
+            }
+            DecodeError::BadMagic(magic) => {
+                write!(f, "magic: {:#010x} instead of {:#010x}", magic, MAGIC)
+            }
+            DecodeError::InvalidField(field) => write!(f, "invalid field: {}", field),
+            DecodeError::Unauthenticated => write!(f, "frame authentication failed"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+fn addrs_len(addrs: &[IfAddr]) -> usize {
+    let addr_len = |ifaddr: &IfAddr| match ifaddr.addr {
+        IpAddr::V4(_) => 8 + 4,
+        IpAddr::V6(_) => 8 + 16,
+    };
+    4 + addrs.iter().map(addr_len).sum::<usize>()
+}
+
+fn write_addrs(w: &mut impl Write, addrs: &[IfAddr]) -> std::io::Result<()> {
+    w.write_all(&(addrs.len() as u32).to_be_bytes())?;
+    for ifaddr in addrs {
+        let netmask = (ifaddr.netmask as u32).to_be_bytes();
+        match ifaddr.addr {
+            IpAddr::V4(addr) => {
+                w.write_all(&4_u32.to_be_bytes())?;
+                w.write_all(&netmask)?;
+                w.write_all(&addr.octets())?;
+            }
+            IpAddr::V6(addr) => {
+                w.write_all(&6_u32.to_be_bytes())?;
+                w.write_all(&netmask)?;
+                w.write_all(&addr.octets())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// DNS servers as an address list
+fn host_addrs(addrs: &[IpAddr]) -> Vec<IfAddr> {
+    addrs
+        .iter()
+        .map(|&addr| IfAddr {
+            addr,
+            netmask: if addr.is_ipv4() { 32 } else { 128 },
+        })
+        .collect()
+}
+
+fn domains_len(domains: &[String]) -> usize {
+    4 + domains.iter().map(|domain| 4 + domain.len()).sum::<usize>()
+}
+
+fn write_domains(w: &mut impl Write, domains: &[String]) -> std::io::Result<()> {
+    w.write_all(&(domains.len() as u32).to_be_bytes())?;
+    for domain in domains {
+        w.write_all(&(domain.len() as u32).to_be_bytes())?;
+        w.write_all(domain.as_bytes())?;
+    }
+    Ok(())
+}
+
+// number of optional lists ending the HelloReply frame that are encoded,
+// empty lists at the end are left out unless followed by the MTU
+fn trailing_lists(
+    assigned: &[IfAddr],
+    routes: &[IfAddr],
+    dns: &[IpAddr],
+    domains: &[String],
+    mtu: Option<u32>,
+) -> usize {
+    if mtu.is_some() {
+        return 4;
+    }
+    let empty = [
+        assigned.is_empty(),
+        routes.is_empty(),
+        dns.is_empty(),
+        domains.is_empty(),
+    ];
+    empty
+        .iter()
+        .rposition(|empty| !empty)
+        .map_or(0, |last| last + 1)
+}
+
+/// Write a single frame into `w`, without flushing
+pub fn write_frame(w: &mut impl Write, frame: &Frame) -> std::io::Result<()> {
+    w.write_all(&frame.kind().to_be_bytes())?;
+    w.write_all(&(frame.body_len() as u32).to_be_bytes())?;
+    match *frame {
+        Frame::Data { counter, payload } => {
+            w.write_all(&counter.to_be_bytes())?;
+            w.write_all(payload)?;
+        }
+        Frame::Exit { reason } => w.write_all(&reason.to_be_bytes())?,
+        Frame::Ping { timestamp } | Frame::Pong { timestamp } => {
+            w.write_all(&timestamp.to_be_bytes())?
+        }
+        Frame::Hello {
+            min_version,
+            max_version,
+            capabilities,
+            ref addrs,
+            mtu,
+        } => {
+            w.write_all(&MAGIC.to_be_bytes())?;
+            w.write_all(&min_version.to_be_bytes())?;
+            w.write_all(&max_version.to_be_bytes())?;
+            w.write_all(&capabilities.bits().to_be_bytes())?;
+            write_addrs(w, addrs)?;
+            if let Some(mtu) = mtu {
+                w.write_all(&mtu.to_be_bytes())?;
+            }
+        }
+        Frame::HelloReply {
+            version,
+            capabilities,
+            ref addrs,
+            ref assigned,
+            ref routes,
+            ref dns,
+            ref domains,
+            mtu,
+        } => {
+            w.write_all(&version.to_be_bytes())?;
+            w.write_all(&capabilities.bits().to_be_bytes())?;
+            write_addrs(w, addrs)?;
+            let count = trailing_lists(assigned, routes, dns, domains, mtu);
+            if count > 0 {
+                write_addrs(w, assigned)?;
+            }
+            if count > 1 {
+                write_addrs(w, routes)?;
+            }
+            if count > 2 {
+                write_addrs(w, &host_addrs(dns))?;
+            }
+            if count > 3 {
+                write_domains(w, domains)?;
+            }
+            if let Some(mtu) = mtu {
+                w.write_all(&mtu.to_be_bytes())?;
+            }
+        }
+        Frame::HandshakeStatus { status } => w.write_all(&status.to_be_bytes())?,
+        Frame::Noise { message } => w.write_all(message)?,
+        Frame::AuthChallenge { nonce } => w.write_all(nonce)?,
+        Frame::AuthResponse { nonce, mac } => {
+            w.write_all(nonce)?;
+            w.write_all(mac)?;
+        }
+        Frame::AuthProof { mac } => w.write_all(mac)?,
+        Frame::Sealed { ciphertext } => w.write_all(ciphertext)?,
+    }
+    Ok(())
+}
+
+/// Append the encoding of `frame` to `out`
+pub fn encode(frame: &Frame, out: &mut Vec<u8>) {
+    // writing into a Vec cannot fail
+    write_frame(out, frame).unwrap();
+}
+
+// parse header, return (type, body length) if enough bytes are available
+fn decode_header(buf: &[u8], max_len: usize) -> Result<Option<(u32, usize)>, DecodeError> {
+    let Some((&kind, rest)) = buf.split_first_chunk::<4>() else {
+        return Ok(None);
+    };
+    let Some((&len, _)) = rest.split_first_chunk::<4>() else {
+        return Ok(None);
+    };
+    let kind = u32::from_be_bytes(kind);
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len {
+        return Err(DecodeError::TooLong { len, max: max_len });
+    }
+    Ok(Some((kind, len)))
+}
+
+fn be_u32(body: &[u8]) -> u32 {
+    u32::from_be_bytes(*body.first_chunk().unwrap())
+}
+
+fn be_u64(body: &[u8]) -> u64 {
+    u64::from_be_bytes(*body.first_chunk().unwrap())
+}
+
+// parse the MTU at the start of `rest`, in a `len` bytes body
+fn decode_mtu(kind: u32, len: usize, rest: &[u8]) -> Result<u32, DecodeError> {
+    let Some(&mtu) = rest.first_chunk::<4>() else {
+        return Err(DecodeError::BadLength { kind, len });
+    };
+    let mtu = u32::from_be_bytes(mtu);
+    if !(MIN_MTU..=MAX_MTU).contains(&mtu) {
+        return Err(DecodeError::InvalidField("MTU"));
+    }
+    Ok(mtu)
+}
+
+// parse the interface address list starting at `offset` in the body, return
+// it with the bytes following it
+fn decode_addrs(
+    kind: u32,
+    body: &[u8],
+    offset: usize,
+) -> Result<(Vec<IfAddr>, &[u8]), DecodeError> {
+    let Some((&count, mut rest)) = body[offset..].split_first_chunk::<4>() else {
+        return Err(DecodeError::BadLength {
+            kind,
+            len: body.len(),
+        });
+    };
+    let mut addrs = Vec::new();
+    for _ in 0..u32::from_be_bytes(count) {
+        let (ifaddr, next) = decode_addr(kind, body.len(), rest)?;
+        addrs.push(ifaddr);
+        rest = next;
+    }
+    Ok((addrs, rest))
+}
+
+// parse the domain list starting at `offset` in the body, return it with the
+// bytes following it
+fn decode_domains(
+    kind: u32,
+    body: &[u8],
+    offset: usize,
+) -> Result<(Vec<String>, &[u8]), DecodeError> {
+    let bad_length = DecodeError::BadLength {
+        kind,
+        len: body.len(),
+    };
+    let Some((&count, mut rest)) = body[offset..].split_first_chunk::<4>() else {
+        return Err(bad_length);
+    };
+    let mut domains = Vec::new();
+    for _ in 0..u32::from_be_bytes(count) {
+        let Some((&len, next)) = rest.split_first_chunk::<4>() else {
+            return Err(bad_length);
+        };
+        let len = u32::from_be_bytes(len) as usize;
+        if len > next.len() {
+            return Err(bad_length);
+        }
+        let (domain, next) = next.split_at(len);
+        // written as is in the resolver configuration
+        match std::str::from_utf8(domain) {
+            Ok(domain) if valid_domain(domain) => domains.push(domain.to_string()),
+            _ => return Err(DecodeError::InvalidField("domain")),
+        }
+        rest = next;
+    }
+    Ok((domains, rest))
+}
+
+// parse a single address of a list in a `len` bytes body
+fn decode_addr(kind: u32, len: usize, body: &[u8]) -> Result<(IfAddr, &[u8]), DecodeError> {
+    let bad_length = DecodeError::BadLength { kind, len };
+    let Some((&family, rest)) = body.split_first_chunk::<4>() else {
+        return Err(bad_length);
+    };
+    let Some((&netmask, rest)) = rest.split_first_chunk::<4>() else {
+        return Err(bad_length);
+    };
+    let netmask = u32::from_be_bytes(netmask);
+    let (addr, max_netmask, rest): (IpAddr, u32, &[u8]) = match u32::from_be_bytes(family) {
+        4 => match rest.split_first_chunk::<4>() {
+            Some((&addr, rest)) => (Ipv4Addr::from(addr).into(), 32, rest),
+            None => return Err(bad_length),
+        },
+        6 => match rest.split_first_chunk::<16>() {
+            Some((&addr, rest)) => (Ipv6Addr::from(addr).into(), 128, rest),
+            None => return Err(bad_length),
+        },
+        _ => return Err(DecodeError::InvalidField("address family")),
+    };
+    if netmask > max_netmask {
+        return Err(DecodeError::InvalidField("netmask"));
+    }
+    let netmask = netmask as u8;
+    Ok((IfAddr { addr, netmask }, rest))
+}
+
+fn decode_body(kind: u32, body: &[u8]) -> Result<Frame<'_>, DecodeError> {
+    let expect_len = |len: usize| {
+        if body.len() == len {
+            Ok(())
+        } else {
+            Err(DecodeError::BadLength {
+                kind,
+                len: body.len(),
+            })
+        }
+    };
+    match kind {
+        DATA => {
+            let Some((&counter, payload)) = body.split_first_chunk::<8>() else {
+                return Err(DecodeError::BadLength {
+                    kind,
+                    len: body.len(),
+                });
+            };
+            Ok(Frame::Data {
+                counter: u64::from_be_bytes(counter),
+                payload,
+            })
+        }
+        EXIT => {
+            expect_len(4)?;
+            Ok(Frame::Exit {
+                reason: be_u32(body),
+            })
+        }
+        PING => {
+            expect_len(8)?;
+            Ok(Frame::Ping {
+                timestamp: be_u64(body),
+            })
+        }
+        PONG => {
+            expect_len(8)?;
+            Ok(Frame::Pong {
+                timestamp: be_u64(body),
+            })
+        }
+        HELLO => {
+            // check magic first, to tell apart other protocols
+            if let Some(&magic) = body.first_chunk::<4>() {
+                let magic = u32::from_be_bytes(magic);
+                if magic != MAGIC {
+                    return Err(DecodeError::BadMagic(magic));
+                }
+            }
+            if body.len() < 16 {
+                return Err(DecodeError::BadLength {
+                    kind,
+                    len: body.len(),
+                });
+            }
+            // unknown features are kept, they are never in common
+            let capabilities = Capabilities::from_bits_retain(be_u32(&body[12..]));
+            let (addrs, rest) = decode_addrs(kind, body, 16)?;
+            // fields appended by later versions are ignored
+            let mtu = capabilities
+                .contains(Capabilities::MTU)
+                .then(|| decode_mtu(kind, body.len(), rest))
+                .transpose()?;
+            Ok(Frame::Hello {
+                min_version: be_u32(&body[4..]),
+                max_version: be_u32(&body[8..]),
+                capabilities,
+                addrs,
+                mtu,
+            })
+        }
+        HELLO_REPLY => {
+            if body.len() < 8 {
+                return Err(DecodeError::BadLength {
+                    kind,
+                    len: body.len(),
+                });
+            }
+            let (addrs, mut rest) = decode_addrs(kind, body, 8)?;
+            // trailing lists are optional
+            let offset = |rest: &[u8]| body.len() - rest.len();
+            let (mut assigned, mut routes, mut dns, mut domains) =
+                (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+            if !rest.is_empty() {
+                (assigned, rest) = decode_addrs(kind, body, offset(rest))?;
+            }
+            if !rest.is_empty() {
+                (routes, rest) = decode_addrs(kind, body, offset(rest))?;
+            }
+            if !rest.is_empty() {
+                let servers;
+                (servers, rest) = decode_addrs(kind, body, offset(rest))?;
+                dns = servers.iter().map(|server| server.addr).collect();
+            }
+            if !rest.is_empty() {
+                (domains, rest) = decode_domains(kind, body, offset(rest))?;
+            }
+            let mtu = (!rest.is_empty())
+                .then(|| decode_mtu(kind, body.len(), rest))
+                .transpose()?;
+            if rest.len() > mtu.map_or(0, |_| 4) {
+                return Err(DecodeError::BadLength {
+                    kind,
+                    len: body.len(),
+                });
+            }
+            Ok(Frame::HelloReply {
+                version: be_u32(body),
+                capabilities: Capabilities::from_bits_retain(be_u32(&body[4..])),
+                addrs,
+                assigned,
+                routes,
+                dns,
+                domains,
+                mtu,
+            })
+        }
+        HANDSHAKE_STATUS => {
+            expect_len(4)?;
+            Ok(Frame::HandshakeStatus {
+                status: be_u32(body),
+            })
+        }
+        NOISE => Ok(Frame::Noise { message: body }),
+        AUTH_CHALLENGE => {
+            expect_len(AUTH_NONCE_LEN)?;
+            Ok(Frame::AuthChallenge {
+                nonce: body.first_chunk().unwrap(),
+            })
+        }
+        AUTH_RESPONSE => {
+            expect_len(AUTH_NONCE_LEN + AUTH_MAC_LEN)?;
+            let (nonce, mac) = body.split_first_chunk().unwrap();
+            Ok(Frame::AuthResponse {
+                nonce,
+                mac: mac.first_chunk().unwrap(),
+            })
+        }
+        AUTH_PROOF => {
+            expect_len(AUTH_MAC_LEN)?;
+            Ok(Frame::AuthProof {
+                mac: body.first_chunk().unwrap(),
+            })
+        }
+        SEALED => Ok(Frame::Sealed { ciphertext: body }),
+        _ => Err(DecodeError::UnknownType(kind)),
+    }
+}
+
+/// Decode the first frame in `buf`
+///
+/// Return the frame and the number of bytes it occupies, or `None` if
+/// `buf` does not contain a whole frame yet. Bodies longer than `max_len`
+/// are rejected as soon as the header is available.
+pub fn decode(buf: &[u8], max_len: usize) -> Result<Option<(Frame<'_>, usize)>, DecodeError> {
+    let Some((kind, len)) = decode_header(buf, max_len)? else {
+        return Ok(None);
+    };
+    let Some(body) = buf.get(HEADER_LEN..HEADER_LEN + len) else {
+        return Ok(None);
+    };
+    Ok(Some((decode_body(kind, body)?, HEADER_LEN + len)))
+}
+
+/// Reassemble frames out of a byte stream
+pub struct FrameReader {
+    buf: Vec<u8>,
+    // pending bytes are buf[start..end]
+    start: usize,
+    end: usize,
+    max_len: usize,
+}
+
+impl FrameReader {
+    pub fn new(max_len: usize) -> Self {
+        FrameReader {
+            buf: vec![0; 2 * (HEADER_LEN + max_len)],
+            start: 0,
+            end: 0,
+            max_len,
+        }
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Change the maximum accepted body length, pending bytes are kept
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+        let len = 2 * (HEADER_LEN + max_len);
+        if self.buf.len() < len {
+            self.buf.resize(len, 0);
+        }
+    }
+
+    /// True if next_frame would return a frame
+    pub fn ready(&self) -> Result<bool, DecodeError> {
+        match decode_header(&self.buf[self.start..self.end], self.max_len)? {
+            Some((_, len)) => Ok(self.pending() >= HEADER_LEN + len),
+            None => Ok(false),
+        }
+    }
+
+    /// Bytes received but not yet returned as frames
+    pub fn pending(&self) -> usize {
+        self.end - self.start
+    }
+
+    // make room at the end of the buffer for at least one whole frame
+    fn compact(&mut self) {
+        if self.buf.len() - self.end < HEADER_LEN + self.max_len {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+    }
+
+    /// Append bytes produced by `f`, which is given at least `len` bytes
+    /// of free space and returns how many of them it filled
+    pub fn extend<E>(
+        &mut self,
+        len: usize,
+        f: impl FnOnce(&mut [u8]) -> Result<usize, E>,
+    ) -> Result<usize, E> {
+        self.compact();
+        if self.buf.len() - self.end < len {
+            self.buf.resize(self.end + len, 0);
+        }
+        let sz = f(&mut self.buf[self.end..])?;
+        self.end += sz;
+        Ok(sz)
+    }
+
+    /// Perform a single read from `r`, return the number of bytes read
+    /// (0 on end of stream)
+    pub fn fill(&mut self, r: &mut impl Read) -> std::io::Result<usize> {
+        self.compact();
+        let sz = r.read(&mut self.buf[self.end..])?;
+        self.end += sz;
+        Ok(sz)
+    }
+
+    /// Return next complete frame, if already received
+    pub fn next_frame(&mut self) -> Result<Option<Frame<'_>>, DecodeError> {
+        match decode(&self.buf[self.start..self.end], self.max_len)? {
+            Some((frame, sz)) => {
+                self.start += sz;
+                Ok(Some(frame))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Block until a whole frame is read from `r`
+    ///
+    /// Never reads past the end of the returned frame, so the stream can be
+    /// handed over to someone else afterwards.
+    pub fn read_frame(&mut self, r: &mut impl Read) -> anyhow::Result<Frame<'_>> {
+        loop {
+            let needed = match decode_header(&self.buf[self.start..self.end], self.max_len)? {
+                Some((_, len)) => HEADER_LEN + len,
+                None => HEADER_LEN,
+            };
+            if self.pending() >= needed {
+                break;
+            }
+            self.compact();
+            let missing = needed - self.pending();
+            r.read_exact(&mut self.buf[self.end..self.end + missing])?;
+            self.end += missing;
+        }
+        Ok(self.next_frame()?.expect("whole frame available"))
+    }
+}
@@ -0,0 +1,155 @@
+// Pre-shared key authentication
+//
+// Both endpoints prove the knowledge of a shared secret before any interface
+// property is exchanged:
+//
+//      1. client -> server: AuthChallenge (client nonce)
+//      2. server -> client: AuthResponse (server nonce, server MAC)
+//      3. client -> server: AuthProof (client MAC)
+//
+// Every MAC is an HMAC-SHA256 keyed with the secret over a role label, both
+// nonces and the channel binding (Noise handshake hash and/or TLS exporter
+// value), so proofs can neither be replayed in another session nor
+// reflected to the other endpoint, and a relay between two encrypted
+// connections cannot forward them. The key only authenticates the peers,
+// combine it with Noise or TLS to keep the traffic confidential.
+
+use crate::channel::{Channel, Stream};
+use crate::protocol::{AUTH_MAC_LEN, AUTH_NONCE_LEN, Frame};
+use anyhow::{Context, Result, bail};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::path::Path;
+
+/// Environment variable holding the key when no key file is given
+pub const PSK_ENV: &str = "RUST_TCP_VPN_PSK";
+/// Shortest accepted key
+pub const MIN_KEY_LEN: usize = 16;
+
+const CLIENT_LABEL: &[u8] = b"rust-tcp-vpn psk client";
+const SERVER_LABEL: &[u8] = b"rust-tcp-vpn psk server";
+
+type HmacSha256 = Hmac<Sha256>;
+
+// nonces exchanged by the two endpoints and binding of the channel they
+// were exchanged on
+struct Session {
+    client_nonce: [u8; AUTH_NONCE_LEN],
+    server_nonce: [u8; AUTH_NONCE_LEN],
+    binding: Vec<u8>,
+}
+
+pub struct Psk {
+    key: Vec<u8>,
+}
+
+impl Psk {
+    pub fn new(key: &[u8]) -> Result<Self> {
+        if key.len() < MIN_KEY_LEN {
+            bail!("Pre-shared key must be at least {} bytes", MIN_KEY_LEN);
+        }
+        Ok(Psk { key: key.to_vec() })
+    }
+
+    /// Load the key from a file, trailing whitespace is ignored
+    pub fn load(path: &Path) -> Result<Self> {
+        let key = std::fs::read(path).with_context(|| format!("Cannot read {}", path.display()))?;
+        Psk::new(key.trim_ascii_end()).with_context(|| format!("Invalid key in {}", path.display()))
+    }
+
+    /// Load the key from the PSK_ENV environment variable, if set
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(key) = std::env::var_os(PSK_ENV) else {
+            return Ok(None);
+        };
+        let key = Psk::new(key.as_encoded_bytes().trim_ascii_end())
+            .with_context(|| format!("Invalid key in {}", PSK_ENV))?;
+        Ok(Some(key))
+    }
+
+    fn hmac(&self, label: &[u8], session: &Session) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC takes keys of any length");
+        mac.update(label);
+        mac.update(&session.client_nonce);
+        mac.update(&session.server_nonce);
+        mac.update(&session.binding);
+        mac
+    }
+
+    fn mac(&self, label: &[u8], session: &Session) -> [u8; AUTH_MAC_LEN] {
+        self.hmac(label, session).finalize().into_bytes().into()
+    }
+
+    // constant time comparison
+    fn verify(&self, label: &[u8], session: &Session, mac: &[u8]) -> bool {
+        self.hmac(label, session).verify_slice(mac).is_ok()
+    }
+}
+
+fn random_nonce() -> Result<[u8; AUTH_NONCE_LEN]> {
+    let mut nonce = [0; AUTH_NONCE_LEN];
+    getrandom::getrandom(&mut nonce).context("Cannot generate random nonce")?;
+    Ok(nonce)
+}
+
+/// Prove the knowledge of the key to the server and check its proof
+pub fn client_auth<S: Stream>(channel: &mut Channel<S>, psk: &Psk) -> Result<()> {
+    let binding = channel.binding()?;
+    let client_nonce = random_nonce()?;
+    channel.send(&Frame::AuthChallenge {
+        nonce: &client_nonce,
+    })?;
+    let frame = channel.recv()?;
+    let Frame::AuthResponse { nonce, mac } = frame else {
+        bail!(
+            "HANDSHAKE error, frame: {} instead of {}",
+            frame.name(),
+            "AuthResponse"
+        );
+    };
+    let session = Session {
+        client_nonce,
+        server_nonce: *nonce,
+        binding,
+    };
+    if !psk.verify(SERVER_LABEL, &session, mac) {
+        bail!("HANDSHAKE error, server failed pre-shared key authentication");
+    }
+    let mac = psk.mac(CLIENT_LABEL, &session);
+    channel.send(&Frame::AuthProof { mac: &mac })
+}
+
+/// Check the client proof of the key, reject unauthenticated clients
+pub fn server_auth<S: Stream>(channel: &mut Channel<S>, psk: &Psk) -> Result<()> {
+    let binding = channel.binding()?;
+    let frame = channel.recv()?;
+    let Frame::AuthChallenge { nonce } = frame else {
+        bail!(
+            "HANDSHAKE error, frame: {} instead of {}",
+            frame.name(),
+            "AuthChallenge"
+        );
+    };
+    let session = Session {
+        client_nonce: *nonce,
+        server_nonce: random_nonce()?,
+        binding,
+    };
+    let mac = psk.mac(SERVER_LABEL, &session);
+    channel.send(&Frame::AuthResponse {
+        nonce: &session.server_nonce,
+        mac: &mac,
+    })?;
+    let frame = channel.recv()?;
+    let Frame::AuthProof { mac } = frame else {
+        bail!(
+            "HANDSHAKE error, frame: {} instead of {}",
+            frame.name(),
+            "AuthProof"
+        );
+    };
+    if !psk.verify(CLIENT_LABEL, &session, mac) {
+        bail!("HANDSHAKE error, client failed pre-shared key authentication");
+    }
+    Ok(())
+}
@@ -0,0 +1,278 @@
+// Destination based dispatch of the packets of a TUN shared by many sessions
+//
+// Every session exchanges packets with its own end of a datagram socket
+// pair instead of the TUN itself: a single router thread reads the TUN and
+// hands each packet to the session owning its destination address, while
+// sessions write the packets they receive straight to the TUN.
+//
+// Packets a session receives for another client are handed to that client
+// directly, never entering the TUN, or dropped if clients must not reach each
+// other.
+//
+// A TAP shared by many sessions works as a switch instead: the MAC addresses
+// seen as source of the frames sent by every session are learned, frames are
+// dispatched by destination MAC address, and broadcast, multicast or unknown
+// destinations reach every session (and the TAP, for frames of a client).
+
+use crate::tunif::{IfAddr, IfConfig, IfKind, MacAddr, TunDevice, ethernet_addresses};
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::net::UnixDatagram;
+use std::sync::{Arc, RwLock};
+
+/// Maximum number of MAC addresses learned from a single session
+pub const MAX_LEARNED_MACS: usize = 256;
+
+/// Destination address of an IPv4 or IPv6 packet
+pub fn destination(packet: &[u8]) -> Option<IpAddr> {
+    match packet.first()? >> 4 {
+        4 => {
+            let addr: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            Some(Ipv4Addr::from(addr).into())
+        }
+        6 => {
+            let addr: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            Some(Ipv6Addr::from(addr).into())
+        }
+        _ => None,
+    }
+}
+
+// router end of the socket pair of a session
+type Router = Arc<UnixDatagram>;
+
+#[derive(Default)]
+struct Routes {
+    // session owning each tunnel address
+    addrs: HashMap<IpAddr, Router>,
+    // (TAP) session each learned MAC address is behind
+    macs: HashMap<MacAddr, Router>,
+    // every session, in attachment order
+    sessions: Vec<Router>,
+}
+
+impl Routes {
+    // send `frame` to every session but `except`
+    fn flood(&self, frame: &[u8], except: Option<&Router>) {
+        for router in &self.sessions {
+            if except.is_none_or(|except| !Arc::ptr_eq(router, except)) {
+                // lost if the session cannot keep up
+                let _ = router.send(frame);
+            }
+        }
+    }
+}
+
+/// Tunnel addresses of the connected clients
+pub struct RoutingTable {
+    routes: RwLock<Routes>,
+    // forward packets between clients, drop them otherwise
+    client_to_client: bool,
+    // Ethernet frames dispatched by MAC address, instead of IP packets
+    ethernet: bool,
+    // virtio-net header preceding every packet, see `IfConfig::offload`
+    header_len: usize,
+    // largest packet read from the TUN
+    max_packet_len: usize,
+}
+
+impl RoutingTable {
+    /// Table of the sessions sharing an interface configured with `config`
+    pub fn new(client_to_client: bool, config: &IfConfig) -> Arc<Self> {
+        Arc::new(RoutingTable {
+            routes: RwLock::default(),
+            client_to_client,
+            ethernet: config.kind == IfKind::Tap,
+            header_len: config.header_len(),
+            max_packet_len: config.max_packet_len(),
+        })
+    }
+
+    /// Route the packets for `addrs` to a new session, `tun` receives the
+    /// packets coming from it
+    ///
+    /// Fails if any address belongs to a connected client. The routes are
+    /// removed when the returned device is dropped.
+    pub fn attach(self: &Arc<Self>, addrs: &[IfAddr], tun: File) -> Result<SessionTun> {
+        let (router, rx) = UnixDatagram::pair()?;
+        // a slow session must not stall the others, its packets are
+        // dropped instead
+        router.set_nonblocking(true)?;
+        let router = Arc::new(router);
+        let mut routes = self.routes.write().unwrap();
+        if let Some(ifaddr) = addrs
+            .iter()
+            .find(|ifaddr| routes.addrs.contains_key(&ifaddr.addr))
+        {
+            bail!("Address {} already in use by another client", ifaddr.addr);
+        }
+        for ifaddr in addrs {
+            routes.addrs.insert(ifaddr.addr, router.clone());
+        }
+        routes.sessions.push(router.clone());
+        Ok(SessionTun {
+            rx,
+            tun,
+            table: self.clone(),
+            router,
+            addrs: addrs.to_vec(),
+        })
+    }
+
+    /// Hand `packet` to the session owning its destination, return false if
+    /// there is none or it cannot keep up
+    pub fn forward(&self, packet: &[u8]) -> bool {
+        if self.ethernet {
+            return self.switch(packet);
+        }
+        let Some(dst) = packet.get(self.header_len..).and_then(destination) else {
+            return false;
+        };
+        let routes = self.routes.read().unwrap();
+        match routes.addrs.get(&dst) {
+            Some(router) => router.send(packet).is_ok(),
+            None => false,
+        }
+    }
+
+    // hand a frame read from the TAP to the session its destination is
+    // behind, or to every session if unknown
+    fn switch(&self, frame: &[u8]) -> bool {
+        let Some((dst, _)) = frame.get(self.header_len..).and_then(ethernet_addresses) else {
+            return false;
+        };
+        let routes = self.routes.read().unwrap();
+        match routes.macs.get(&dst) {
+            Some(router) => router.send(frame).is_ok(),
+            None => {
+                routes.flood(frame, None);
+                !routes.sessions.is_empty()
+            }
+        }
+    }
+
+    // deliver a packet sent by a client to another client, return false if
+    // the destination is not a client
+    fn forward_between(&self, packet: &[u8], from: &Router) -> bool {
+        if self.ethernet {
+            return self.switch_between(packet, from);
+        }
+        let Some(dst) = packet.get(self.header_len..).and_then(destination) else {
+            return false;
+        };
+        let routes = self.routes.read().unwrap();
+        let Some(router) = routes.addrs.get(&dst) else {
+            return false;
+        };
+        if self.client_to_client {
+            // same as packets from the TUN, lost if the client cannot keep up
+            let _ = router.send(packet);
+        }
+        true
+    }
+
+    // learn the source of a frame sent by a client and deliver it to other
+    // clients, return false if it must enter the TAP too
+    fn switch_between(&self, frame: &[u8], from: &Router) -> bool {
+        let Some((dst, src)) = frame.get(self.header_len..).and_then(ethernet_addresses) else {
+            // dropped
+            return true;
+        };
+        self.learn(src, from);
+        let routes = self.routes.read().unwrap();
+        if dst.is_multicast() {
+            if self.client_to_client {
+                routes.flood(frame, Some(from));
+            }
+            return false;
+        }
+        let Some(router) = routes.macs.get(&dst) else {
+            return false;
+        };
+        if self.client_to_client && !Arc::ptr_eq(router, from) {
+            let _ = router.send(frame);
+        }
+        true
+    }
+
+    // the first session a MAC address is seen from keeps it until it
+    // terminates, so a client cannot capture the frames of another
+    fn learn(&self, mac: MacAddr, from: &Router) {
+        if mac.is_multicast() || self.routes.read().unwrap().macs.contains_key(&mac) {
+            return;
+        }
+        let mut routes = self.routes.write().unwrap();
+        let learned = routes
+            .macs
+            .values()
+            .filter(|router| Arc::ptr_eq(router, from))
+            .count();
+        if learned < MAX_LEARNED_MACS {
+            routes.macs.entry(mac).or_insert_with(|| from.clone());
+        }
+    }
+
+    fn detach(&self, addrs: &[IfAddr], router: &Router) {
+        let mut routes = self.routes.write().unwrap();
+        for ifaddr in addrs {
+            routes.addrs.remove(&ifaddr.addr);
+        }
+        routes.macs.retain(|_, owner| !Arc::ptr_eq(owner, router));
+        routes
+            .sessions
+            .retain(|session| !Arc::ptr_eq(session, router));
+    }
+}
+
+/// Read packets from the shared TUN and dispatch them to the sessions
+///
+/// Packets for unknown destinations are dropped. Return only if the TUN
+/// cannot be read anymore.
+pub fn route_packets(tun: &mut impl TunDevice, table: &RoutingTable) -> Result<()> {
+    let mut buffer = vec![0; table.max_packet_len];
+    loop {
+        let sz = tun.recv(&mut buffer)?;
+        table.forward(&buffer[..sz]);
+    }
+}
+
+/// Virtual interface of a single session of a shared TUN
+pub struct SessionTun {
+    // packets routed to this session
+    rx: UnixDatagram,
+    // shared TUN, every write injects exactly one packet
+    tun: File,
+    table: Arc<RoutingTable>,
+    // sends the packets routed to this session
+    router: Router,
+    addrs: Vec<IfAddr>,
+}
+
+impl AsFd for SessionTun {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.rx.as_fd()
+    }
+}
+
+impl TunDevice for SessionTun {
+    fn recv(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.rx.recv(buf)
+    }
+
+    fn send(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        if self.table.forward_between(buf, &self.router) {
+            return Ok(());
+        }
+        self.tun.write_all(buf)
+    }
+}
+
+impl Drop for SessionTun {
+    fn drop(&mut self) {
+        self.table.detach(&self.addrs, &self.router);
+    }
+}
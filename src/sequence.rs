@@ -0,0 +1,96 @@
+// Validation of the counter carried by data frames
+//
+// The sender numbers data frames 1, 2, 3, ... so the receiver can notice
+// lost, reordered and duplicated frames. Counters are tracked with a
+// sliding window (as done by IPsec and WireGuard): the highest counter seen
+// so far plus a bitmap of the WINDOW counters preceding it.
+
+use std::fmt;
+
+// how many counters before the highest one are remembered
+const WINDOW: u64 = 64;
+
+/// Outcome of the validation of a single counter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    // next expected counter, or newer (some frames missing)
+    InOrder,
+    // older than the highest seen, but never received before
+    Late,
+    // already received, or too old to tell: must be discarded
+    Replay,
+}
+
+/// Per session statistics
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SeqStats {
+    // accepted frames
+    pub received: u64,
+    // counters skipped and not (yet) received
+    pub lost: u64,
+    // frames arrived after a newer one
+    pub reordered: u64,
+    // duplicated or replayed frames
+    pub replayed: u64,
+}
+
+impl fmt::Display for SeqStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "received {}, lost {}, reordered {}, replayed {}",
+            self.received, self.lost, self.reordered, self.replayed
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SeqTracker {
+    // highest counter accepted so far, 0 if none
+    highest: u64,
+    // bit i set if counter (highest - i) has been received
+    window: u64,
+    stats: SeqStats,
+}
+
+impl SeqTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> &SeqStats {
+        &self.stats
+    }
+
+    /// Validate `counter` and update statistics accordingly
+    pub fn check(&mut self, counter: u64) -> Verdict {
+        // counters start from 1
+        if counter == 0 {
+            self.stats.replayed += 1;
+            return Verdict::Replay;
+        }
+        if counter > self.highest {
+            let shift = counter - self.highest;
+            self.stats.lost += shift - 1;
+            self.window = if shift < WINDOW {
+                (self.window << shift) | 1
+            } else {
+                1
+            };
+            self.highest = counter;
+            self.stats.received += 1;
+            return Verdict::InOrder;
+        }
+        let age = self.highest - counter;
+        if age >= WINDOW || self.window & (1 << age) != 0 {
+            self.stats.replayed += 1;
+            return Verdict::Replay;
+        }
+        // it was counted as lost when a newer counter arrived
+        self.window |= 1 << age;
+        self.stats.lost -= 1;
+        self.stats.reordered += 1;
+        self.stats.received += 1;
+        Verdict::Late
+    }
+}
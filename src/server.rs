@@ -1,36 +1,359 @@
+use crate::channel::Channel;
 use crate::flows;
-use crate::handshake;
-use crate::tunif::Iface;
+use crate::handshake::{self, Endpoint, Negotiated, Pushed, Watchdog};
+use crate::leases::{self, ClientId, LeaseFile, StoredLease};
+use crate::parsing::{Interface, Security, ServerConfig};
+use crate::pool::{AddressPool, Lease};
+use crate::routing::{self, RoutingTable};
+use crate::tls::Transport;
+use crate::tunif::{IfAddr, Iface};
 use anyhow::Result;
 use anyhow::bail;
-use std::net::{IpAddr, TcpListener};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, mpsc};
+use std::thread;
+use std::time::Duration;
 
-pub fn execute_server(
-    ifname: String,
-    ifaddr: IpAddr,
-    netmask: u8,
-    local: std::net::SocketAddr,
+// connections beyond this many pending handshakes are closed immediately
+pub const MAX_PENDING_HANDSHAKES: usize = 64;
+
+/// Everything needed to run the server side of the handshake
+pub struct Handshaker {
+    pub local: Endpoint,
+    // addresses assigned to clients, None if clients choose them
+    pub pool: Option<Arc<AddressPool>>,
+    // routes and DNS settings of clients
+    pub pushed: Pushed,
+    pub security: Security,
+    // give up on clients not completing the handshake in time
+    pub timeout: Option<Duration>,
+}
+
+/// Connection that completed the handshake
+pub struct Session {
+    pub channel: Channel<Transport>,
+    pub negotiated: Negotiated,
+    pub peer: SocketAddr,
+    // interface addresses of the client
+    pub lease: Lease,
+}
+
+fn establish(sock: TcpStream, handshaker: &Handshaker) -> Result<Session> {
+    let peer = sock.peer_addr()?;
+    let watchdog = handshaker
+        .timeout
+        .map(|timeout| Watchdog::new(&sock, timeout))
+        .transpose()?;
+    let security = &handshaker.security;
+    let ans = Transport::new(sock, security.tls.as_ref()).and_then(|stream| {
+        let identity = stream.peer_fingerprint().map(ClientId::Tls);
+        let mut channel = Channel::new(stream, handshake::HANDSHAKE_MAX_LEN);
+        let (negotiated, lease) = handshake::handler_server_handshake(
+            &mut channel,
+            &handshaker.local,
+            handshaker.pool.as_ref(),
+            &handshaker.pushed,
+            identity,
+            security.noise.as_ref(),
+            security.psk.as_ref(),
+        )?;
+        Ok(Session {
+            channel,
+            negotiated,
+            peer,
+            lease,
+        })
+    });
+    if watchdog.is_some_and(|watchdog| watchdog.expired()) {
+        bail!("HANDSHAKE error, {} timed out", peer);
+    }
+    ans
+}
+
+/// Accept connections on a background thread and run every handshake on
+/// its own thread, so slow or malicious clients cannot delay the others
+///
+/// Sessions are delivered in the order their handshake completes.
+pub fn accept_sessions(listener: TcpListener, handshaker: Handshaker) -> mpsc::Receiver<Session> {
+    let (tx, rx) = mpsc::channel();
+    let handshaker = Arc::new(handshaker);
+    let pending = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || {
+        for sock in listener.incoming() {
+            let sock = match sock {
+                Ok(sock) => sock,
+                Err(err) => {
+                    eprintln!("Cannot accept connection: {}", err);
+                    continue;
+                }
+            };
+            if pending.fetch_add(1, Ordering::Relaxed) >= MAX_PENDING_HANDSHAKES {
+                pending.fetch_sub(1, Ordering::Relaxed);
+                eprintln!("Too many pending handshakes, connection dropped");
+                continue;
+            }
+            let (tx, handshaker, pending) = (tx.clone(), handshaker.clone(), pending.clone());
+            thread::spawn(move || {
+                // a misbehaving client must not take the server down
+                let ans = establish(sock, &handshaker);
+                pending.fetch_sub(1, Ordering::Relaxed);
+                match ans {
+                    // server gone, nobody to hand the session to
+                    Ok(session) => _ = tx.send(session),
+                    Err(err) => eprintln!("Handshake failed: {}", err),
+                }
+            });
+        }
+    });
+    rx
+}
+
+// Sessions being served, all stopped together on local signal
+struct Running {
+    sessions: Mutex<Stops>,
+    // signalled every time a session terminates
+    finished: Condvar,
+    // described in the status file, if any
+    status_file: Option<PathBuf>,
+    pool: Option<Arc<AddressPool>>,
+}
+
+#[derive(Default)]
+struct Stops {
+    // no session is started anymore
+    closing: bool,
+    pipes: HashMap<SocketAddr, Connected>,
+}
+
+struct Connected {
+    // write end of the stop pipe, writing to it makes the flow terminate
+    // as on local signal
+    stop: File,
+    addrs: Vec<IfAddr>,
+    identity: Option<ClientId>,
+}
+
+impl Running {
+    fn new(status_file: Option<PathBuf>, pool: Option<Arc<AddressPool>>) -> Self {
+        let running = Running {
+            sessions: Mutex::default(),
+            finished: Condvar::new(),
+            status_file,
+            pool,
+        };
+        running.write_status(&running.sessions.lock().unwrap());
+        running
+    }
+
+    // return the read end of the stop pipe of the new session, None if the
+    // server is shutting down
+    fn start(&self, session: &Session) -> Result<Option<File>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.closing {
+            return Ok(None);
+        }
+        let (r, w) = nix::unistd::pipe()?;
+        let connected = Connected {
+            stop: w.into(),
+            addrs: session.lease.addrs().to_vec(),
+            identity: session.lease.identity(),
+        };
+        sessions.pipes.insert(session.peer, connected);
+        self.write_status(&sessions);
+        Ok(Some(r.into()))
+    }
+
+    fn finish(&self, peer: &SocketAddr) {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.pipes.remove(peer);
+        self.write_status(&sessions);
+        self.finished.notify_all();
+    }
+
+    // stop every session and wait for them to say goodbye to their client
+    fn stop_all(&self) {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.closing = true;
+        for connected in sessions.pipes.values_mut() {
+            // session already terminating otherwise
+            let _ = connected.stop.write_all(&[1]);
+        }
+        let _sessions = self
+            .finished
+            .wait_while(sessions, |sessions| !sessions.pipes.is_empty())
+            .unwrap();
+    }
+
+    // the status is informative only, serving goes on if it cannot be written
+    fn write_status(&self, sessions: &Stops) {
+        let Some(path) = &self.status_file else {
+            return;
+        };
+        let leases = self.pool.as_ref().map_or(Vec::new(), |pool| pool.leases());
+        if let Err(err) = write_status(path, &sessions.pipes, &leases) {
+            eprintln!("Cannot write status: {:#}", err);
+        }
+    }
+}
+
+// Status file format, one line per connected client then one per lease:
+//
+//      client PEER ADDR/NETMASK[,ADDR/NETMASK] IDENTITY|-
+//      lease IDENTITY ADDR[,ADDR] LAST_SEEN
+fn write_status(
+    path: &Path,
+    clients: &HashMap<SocketAddr, Connected>,
+    leases: &[(ClientId, StoredLease)],
 ) -> Result<()> {
-    let IpAddr::V4(tmp) = ifaddr else {
-        bail!("Cannot accept IPv6");
+    let mut peers: Vec<_> = clients.keys().collect();
+    peers.sort();
+    let mut text = String::new();
+    for peer in peers {
+        let connected = &clients[peer];
+        let addrs: Vec<String> = connected
+            .addrs
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect();
+        let identity = connected
+            .identity
+            .map_or(String::from("-"), |id| id.to_string());
+        text += &format!("client {} {} {}\n", peer, addrs.join(","), identity);
+    }
+    for (id, lease) in leases {
+        text += &format!("lease {} {}\n", id, lease);
+    }
+    leases::write_file(path, &text)
+}
+
+fn serve(
+    session: &mut Session,
+    table: &Arc<RoutingTable>,
+    tun: File,
+    flow: &flows::FlowConfig,
+    stop: &mut File,
+) -> Result<flows::FlowExit> {
+    let mut iffile = table.attach(session.lease.addrs(), tun)?;
+    let config = flows::FlowConfig {
+        capabilities: session.negotiated.capabilities,
+        mtu: session.negotiated.mtu,
+        ..flow.clone()
     };
-    let mut iffile = Iface::new(&ifname, tmp, netmask)?;
+    flows::handle_flow(&mut session.channel, &mut iffile, stop, &config)
+}
+
+// run every session on its own thread, sharing the virtual interface: the
+// packets of every session are written to one of the `queues` in turn
+fn dispatch_sessions(
+    sessions: mpsc::Receiver<Session>,
+    queues: Vec<File>,
+    table: Arc<RoutingTable>,
+    flow: flows::FlowConfig,
+    running: Arc<Running>,
+) -> Result<()> {
+    for (mut session, tun) in sessions.into_iter().zip(queues.iter().cycle()) {
+        let Some(mut stop) = running.start(&session)? else {
+            break;
+        };
+        let negotiated = session.negotiated;
+        println!(
+            "Client {}, protocol version {}, capabilities: {:?}, MTU {}",
+            session.peer, negotiated.version, negotiated.capabilities, negotiated.mtu
+        );
+        let (tun, table, flow, running) = (
+            tun.try_clone()?,
+            table.clone(),
+            flow.clone(),
+            running.clone(),
+        );
+        thread::spawn(move || {
+            let peer = session.peer;
+            match serve(&mut session, &table, tun, &flow, &mut stop) {
+                Ok(_) => println!("Client {} disconnected", peer),
+                // a broken session must not take the server down
+                Err(err) => eprintln!("Session with {} failed: {}", peer, err),
+            }
+            // release the addresses before the status is updated
+            drop(session);
+            running.finish(&peer);
+        });
+    }
+    Ok(())
+}
+
+pub fn execute_server(
+    interface: Interface,
+    config: ServerConfig,
+    flow: flows::FlowConfig,
+    security: Security,
+    handshake_timeout: Option<Duration>,
+) -> Result<()> {
+    let Interface {
+        ifname,
+        addrs,
+        config: ifconfig,
+    } = interface;
+    let iffile = Iface::new(&ifname, &addrs, &ifconfig)?;
     // wait for remote connection
-    let listener = TcpListener::bind(local)?;
+    let pool = if config.pools.is_empty() {
+        None
+    } else {
+        let leases = config
+            .lease_file
+            .map(|path| LeaseFile::load(path, config.lease_expiry))
+            .transpose()?;
+        Some(AddressPool::new(&config.pools, &addrs, leases)?)
+    };
+    let running = Arc::new(Running::new(config.status_file, pool.clone()));
+    let listener = TcpListener::bind(config.local)?;
     // spawn thread handler
     let mut sigfile = crate::signals::spawn_sig_handler()?;
-    // allow crashing the process if no client is connected
-    crate::signals::handle_interrupt(false);
-    for stream in listener.incoming() {
-        let mut stream = stream?;
-        // if true ok
-        handshake::handler_server_handshake(&mut stream, &ifaddr, netmask)?;
-        crate::signals::handle_interrupt(true);
-        let ans = flows::handle_flow(&mut stream, &mut iffile, &mut sigfile);
-        crate::signals::handle_interrupt(false);
-        if !ans? {
-            break;
-        }
+    // threads spawned from now on inherit the signal mask
+    let sessions = accept_sessions(
+        listener,
+        Handshaker {
+            local: Endpoint {
+                addrs,
+                kind: ifconfig.kind,
+                offload: ifconfig.offload,
+                mtu: ifconfig.mtu,
+                host_config: true,
+            },
+            pool,
+            pushed: config.pushed,
+            security,
+            timeout: handshake_timeout,
+        },
+    );
+    let table = RoutingTable::new(config.client_to_client, &ifconfig);
+    // packets read from the interface are dispatched to the sessions, by
+    // one thread per queue
+    for queue in iffile.queues() {
+        let mut tun = queue.try_clone()?;
+        let router = table.clone();
+        thread::spawn(move || {
+            if let Err(err) = routing::route_packets(&mut tun, &router) {
+                eprintln!("Cannot read from virtual interface: {}", err);
+            }
+        });
     }
+    let queues = iffile
+        .queues()
+        .map(|queue| queue.try_clone())
+        .collect::<std::io::Result<_>>()?;
+    let dispatcher = running.clone();
+    thread::spawn(move || {
+        if let Err(err) = dispatch_sessions(sessions, queues, table, flow, dispatcher) {
+            eprintln!("Cannot start session: {}", err);
+        }
+    });
+    // serve until local signal
+    crate::signals::consume_sigpipe(&mut sigfile);
+    running.stop_all();
     Ok(())
 }
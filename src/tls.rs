@@ -0,0 +1,469 @@
+// TLS transport based on rustls
+//
+// The TCP connection can optionally be wrapped in TLS, so the tunnel looks
+// like any other TLS connection to middleboxes and can rely on an existing
+// PKI. Peers are verified either against a CA or by pinning the SHA-256
+// fingerprint of their certificate. Client certificates (mTLS) are required
+// by the server whenever it is given a CA or pins for clients.
+
+use crate::channel::Stream;
+use anyhow::{Context, Result, bail};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::WebPkiClientVerifier;
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::{
+    ClientConfig, ClientConnection, Connection, DigitallySignedStruct, DistinguishedName,
+    RootCertStore, ServerConfig, ServerConnection, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::os::fd::{AsFd, BorrowedFd};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub type Fingerprint = [u8; 32];
+
+// label of the keying material exported to bind pre-shared key proofs to
+// the TLS session (RFC 8446, section 7.5)
+const EXPORTER_LABEL: &[u8] = b"EXPORTER-rust-tcp-vpn-psk";
+
+/// TLS related command line options
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    // own certificate chain and private key (PEM), mandatory for servers
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    // CA certificates (PEM) the peer certificate must chain to
+    pub ca: Option<PathBuf>,
+    // SHA-256 fingerprints of accepted peer certificates, alternative to ca
+    pub pins: Vec<Fingerprint>,
+    // (client) name expected in the server certificate
+    pub server_name: Option<String>,
+}
+
+/// SHA-256 fingerprint of a DER encoded certificate
+pub fn fingerprint(cert: &[u8]) -> Fingerprint {
+    Sha256::digest(cert).into()
+}
+
+/// Parse a fingerprint given as 64 hex digits, optionally separated by ':'
+pub fn parse_fingerprint(text: &str) -> Result<Fingerprint> {
+    let digits: String = text.chars().filter(|&c| c != ':').collect();
+    if digits.len() != 64 || !digits.is_ascii() {
+        bail!("SHA-256 fingerprint must be 64 hex digits");
+    }
+    let mut ans = [0; 32];
+    for (i, byte) in ans.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[2 * i..2 * i + 2], 16)?;
+    }
+    Ok(ans)
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn load_certs(path: &PathBuf) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Cannot load certificates from {}", path.display()))?;
+    if certs.is_empty() {
+        bail!("No certificate found in {}", path.display());
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &PathBuf) -> Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+        .with_context(|| format!("Cannot load private key from {}", path.display()))
+}
+
+fn load_roots(path: &PathBuf) -> Result<Arc<RootCertStore>> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+    Ok(Arc::new(roots))
+}
+
+fn check_pin(pins: &[Fingerprint], cert: &CertificateDer<'_>) -> Result<(), rustls::Error> {
+    if pins.contains(&fingerprint(cert)) {
+        Ok(())
+    } else {
+        Err(rustls::Error::General(
+            "certificate fingerprint not pinned".into(),
+        ))
+    }
+}
+
+// Accept peer certificates whose fingerprint is pinned, regardless of
+// issuer, names and validity period. Handshake signatures are still checked
+// so the peer must own the private key of the certificate.
+#[derive(Debug)]
+struct PinVerifier {
+    pins: Vec<Fingerprint>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl PinVerifier {
+    fn new(pins: Vec<Fingerprint>, provider: &CryptoProvider) -> Self {
+        PinVerifier {
+            pins,
+            algorithms: provider.signature_verification_algorithms,
+        }
+    }
+}
+
+impl ServerCertVerifier for PinVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        check_pin(&self.pins, end_entity)?;
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+impl ClientCertVerifier for PinVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        check_pin(&self.pins, end_entity)?;
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// Ready to use TLS configuration of either endpoint
+#[derive(Clone)]
+pub enum TlsConfig {
+    Client {
+        config: Arc<ClientConfig>,
+        server_name: ServerName<'static>,
+    },
+    Server(Arc<ServerConfig>),
+}
+
+impl TlsConfig {
+    /// Client side configuration, `host` is the server name used when no
+    /// other name is given
+    pub fn client(opts: &TlsOptions, host: &str) -> Result<Self> {
+        let provider = provider();
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = match (&opts.ca, opts.pins.is_empty()) {
+            (Some(_), false) => bail!("TLS: CA and pinned fingerprints are mutually exclusive"),
+            (Some(ca), true) => builder.with_root_certificates(load_roots(ca)?),
+            (None, false) => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinVerifier::new(
+                    opts.pins.clone(),
+                    &provider,
+                ))),
+            (None, true) => {
+                bail!("TLS: a CA or a pinned fingerprint is needed to verify the server")
+            }
+        };
+        // client certificate for mTLS
+        let config = match (&opts.cert, &opts.key) {
+            (Some(cert), Some(key)) => {
+                builder.with_client_auth_cert(load_certs(cert)?, load_key(key)?)?
+            }
+            (None, None) => builder.with_no_client_auth(),
+            _ => bail!("TLS: certificate and private key must be given together"),
+        };
+        let name = opts.server_name.as_deref().unwrap_or(host);
+        let server_name = ServerName::try_from(name.to_string())
+            .with_context(|| format!("Invalid TLS server name: {}", name))?;
+        Ok(TlsConfig::Client {
+            config: Arc::new(config),
+            server_name,
+        })
+    }
+
+    /// Server side configuration, client certificates are required if a
+    /// CA or pinned fingerprints are given
+    pub fn server(opts: &TlsOptions) -> Result<Self> {
+        let provider = provider();
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = match (&opts.ca, opts.pins.is_empty()) {
+            (Some(_), false) => bail!("TLS: CA and pinned fingerprints are mutually exclusive"),
+            (Some(ca), true) => builder.with_client_cert_verifier(
+                WebPkiClientVerifier::builder_with_provider(load_roots(ca)?, provider).build()?,
+            ),
+            (None, false) => builder.with_client_cert_verifier(Arc::new(PinVerifier::new(
+                opts.pins.clone(),
+                &provider,
+            ))),
+            (None, true) => builder.with_no_client_auth(),
+        };
+        let (Some(cert), Some(key)) = (&opts.cert, &opts.key) else {
+            bail!("TLS: server requires both certificate and private key");
+        };
+        let config = builder.with_single_cert(load_certs(cert)?, load_key(key)?)?;
+        Ok(TlsConfig::Server(Arc::new(config)))
+    }
+
+    /// Run the TLS handshake over `sock`
+    pub fn wrap(&self, sock: TcpStream) -> Result<TlsStream> {
+        let conn: Connection = match self {
+            TlsConfig::Client {
+                config,
+                server_name,
+            } => ClientConnection::new(config.clone(), server_name.clone())?.into(),
+            TlsConfig::Server(config) => ServerConnection::new(config.clone())?.into(),
+        };
+        let mut stream = TlsStream { conn, sock };
+        while stream.conn.is_handshaking() {
+            stream
+                .conn
+                .complete_io(&mut stream.sock)
+                .context("TLS handshake failed")?;
+        }
+        Ok(stream)
+    }
+}
+
+pub struct TlsStream {
+    conn: Connection,
+    sock: TcpStream,
+}
+
+impl TlsStream {
+    /// Fingerprint of the certificate the peer authenticated with, if any
+    pub fn peer_fingerprint(&self) -> Option<Fingerprint> {
+        let certs = self.conn.peer_certificates()?;
+        certs.first().map(|cert| fingerprint(cert))
+    }
+
+    // send everything rustls has encrypted so far
+    fn write_pending(&mut self) -> std::io::Result<()> {
+        while self.conn.wants_write() {
+            self.conn.write_tls(&mut self.sock)?;
+        }
+        Ok(())
+    }
+
+    // same, but records a non-blocking socket does not take yet are left
+    // for a later flush
+    fn try_write_pending(&mut self) -> std::io::Result<()> {
+        match self.write_pending() {
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => Ok(()),
+            res => res,
+        }
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.conn.reader().read(buf) {
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {}
+            res => return res,
+        }
+        // no plaintext available yet: a single read from the socket, so a
+        // partial record does not hold the caller until the rest arrives
+        if self.conn.read_tls(&mut self.sock)? == 0 {
+            return Ok(0);
+        }
+        self.conn
+            .process_new_packets()
+            .map_err(std::io::Error::other)?;
+        // e.g. key updates must be answered
+        self.try_write_pending()?;
+        match self.conn.reader().read(buf) {
+            // partial record or records without data: progress without
+            // plaintext, to be retried (unlike WouldBlock, which a read
+            // timeout also returns)
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                Err(std::io::ErrorKind::Interrupted.into())
+            }
+            res => res,
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut sz = self.conn.writer().write(buf)?;
+        if sz == 0 && !buf.is_empty() {
+            // rustls buffers are full, make room first
+            self.write_pending()?;
+            sz = self.conn.writer().write(buf)?;
+        }
+        // the data is accepted, whatever the socket does next
+        self.try_write_pending()?;
+        Ok(sz)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.conn.writer().flush()?;
+        self.write_pending()?;
+        self.sock.flush()
+    }
+}
+
+impl AsFd for TlsStream {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.sock.as_fd()
+    }
+}
+
+impl Stream for TlsStream {
+    fn buffered(&mut self) -> bool {
+        // decrypted data rustls has not handed out yet
+        self.conn
+            .process_new_packets()
+            .is_ok_and(|state| state.plaintext_bytes_to_read() > 0)
+    }
+
+    fn wants_write(&self) -> bool {
+        // records encrypted but not sent yet
+        self.conn.wants_write()
+    }
+
+    fn binding(&self) -> Result<Vec<u8>> {
+        let binding = self
+            .conn
+            .export_keying_material(vec![0; 32], EXPORTER_LABEL, None)?;
+        Ok(binding)
+    }
+}
+
+/// Connection to the remote endpoint, with or without TLS
+pub enum Transport {
+    Plain(TcpStream),
+    Tls(Box<TlsStream>),
+}
+
+impl Transport {
+    pub fn new(sock: TcpStream, tls: Option<&TlsConfig>) -> Result<Self> {
+        Ok(match tls {
+            Some(config) => Transport::Tls(Box::new(config.wrap(sock)?)),
+            None => Transport::Plain(sock),
+        })
+    }
+
+    /// Fingerprint of the TLS certificate of the peer, if any
+    pub fn peer_fingerprint(&self) -> Option<Fingerprint> {
+        match self {
+            Transport::Plain(_) => None,
+            Transport::Tls(stream) => stream.peer_fingerprint(),
+        }
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Plain(sock) => sock.read(buf),
+            Transport::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Plain(sock) => sock.write(buf),
+            Transport::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Transport::Plain(sock) => sock.flush(),
+            Transport::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsFd for Transport {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            Transport::Plain(sock) => sock.as_fd(),
+            Transport::Tls(stream) => stream.as_fd(),
+        }
+    }
+}
+
+impl Stream for Transport {
+    fn buffered(&mut self) -> bool {
+        match self {
+            Transport::Plain(sock) => sock.buffered(),
+            Transport::Tls(stream) => stream.buffered(),
+        }
+    }
+
+    fn wants_write(&self) -> bool {
+        match self {
+            Transport::Plain(sock) => sock.wants_write(),
+            Transport::Tls(stream) => stream.wants_write(),
+        }
+    }
+
+    fn binding(&self) -> Result<Vec<u8>> {
+        match self {
+            Transport::Plain(sock) => sock.binding(),
+            Transport::Tls(stream) => stream.binding(),
+        }
+    }
+}
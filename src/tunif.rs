@@ -1,46 +1,14 @@
 use std::ffi::{CStr, CString};
+use std::fmt;
 use std::fs::File;
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::os::fd::{AsFd, AsRawFd};
+use std::str::FromStr;
 
-use anyhow::{Result, bail};
-use socket2::SockAddr;
-
-#[inline(always)]
-fn get_netmask(n: u8) -> u32 {
-    assert!(n <= 32);
-    0xFFFFFFFFu32.wrapping_shl(32 - n as u32)
-}
-
-struct Socket(i32);
-
-impl Socket {
-    fn new() -> Result<Self> {
-        let socket = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
-        if socket < 0 {
-            bail!("Error creating socket")
-        }
-        Ok(Socket(socket))
-    }
-
-    fn ioctl(&self, request: u64, mut ifr: libc::ifreq) -> Result<()> {
-        let tmp: *mut libc::ifreq = &raw mut ifr;
-        let res = unsafe { libc::ioctl(self.0, request, tmp) };
-        if res < 0 {
-            let err = std::io::Error::last_os_error();
-            bail!("Error setting interface address: {}", err);
-        }
-        Ok(())
-    }
-}
-
-impl Drop for Socket {
-    fn drop(&mut self) {
-        unsafe {
-            libc::close(self.0);
-        }
-    }
-}
+use crate::dns::{DnsBackend, DnsConfig};
+use crate::netlink::{Link, Netlink};
+use anyhow::{Context, Result, bail};
 
 unsafe fn ifr_create(ifname: &CStr, flags_fn: impl FnOnce(&mut libc::ifreq)) -> libc::ifreq {
     let mut ifr: libc::ifreq = unsafe { core::mem::zeroed() };
@@ -52,10 +20,21 @@ unsafe fn ifr_create(ifname: &CStr, flags_fn: impl FnOnce(&mut libc::ifreq)) ->
     ifr
 }
 
-fn set_interface_name(file: &File, ifname: &CStr) -> Result<()> {
+fn set_interface_name(file: &File, ifname: &CStr, config: &IfConfig) -> Result<()> {
+    let mut flags = match config.kind {
+        IfKind::Tun => libc::IFF_TUN,
+        IfKind::Tap => libc::IFF_TAP,
+    };
+    // every queue is attached with the same flags
+    if config.queues > 1 {
+        flags |= libc::IFF_MULTI_QUEUE;
+    }
+    if config.offload {
+        flags |= libc::IFF_VNET_HDR;
+    }
     let ifr = unsafe {
         ifr_create(ifname, |ifr| {
-            ifr.ifr_ifru.ifru_flags = (libc::IFF_TUN | libc::IFF_NO_PI) as i16;
+            ifr.ifr_ifru.ifru_flags = (flags | libc::IFF_NO_PI) as i16;
         })
     };
     let res = unsafe { libc::ioctl(file.as_raw_fd(), libc::TUNSETIFF, &ifr) };
@@ -66,78 +45,484 @@ fn set_interface_name(file: &File, ifname: &CStr) -> Result<()> {
     Ok(())
 }
 
-fn set_interface_address(socket: &Socket, ifname: &CStr, addr: &Ipv4Addr) -> Result<()> {
-    let sockaddr: SockAddr = SocketAddrV4::new(*addr, 0).into();
-    let tmp = unsafe { core::ptr::read(sockaddr.as_ptr()) };
-    let ifr = unsafe { ifr_create(ifname, |ifr| ifr.ifr_ifru.ifru_addr = tmp) };
-    socket.ioctl(libc::SIOCSIFADDR, ifr)?;
+// let the kernel hand over packets with partial checksums and TCP segments
+// up to 64 KiB, described by their virtio-net header
+fn set_offload(file: &File) -> Result<()> {
+    let flags = libc::TUN_F_CSUM | libc::TUN_F_TSO4 | libc::TUN_F_TSO6;
+    let res = unsafe {
+        libc::ioctl(
+            file.as_raw_fd(),
+            libc::TUNSETOFFLOAD,
+            flags as libc::c_ulong,
+        )
+    };
+    if res < 0 {
+        let err = std::io::Error::last_os_error();
+        bail!("Error setting interface offload: {}", err);
+    }
     Ok(())
 }
 
-fn set_subnet_mask(socket: &Socket, ifname: &CStr, netmask: u8) -> Result<()> {
-    let mask = get_netmask(netmask).to_be_bytes();
-    let ipv4addr = Ipv4Addr::new(mask[0], mask[1], mask[2], mask[3]);
-    let sockaddr: SockAddr = SocketAddrV4::new(ipv4addr, 0).into();
-    let tmp = unsafe { core::ptr::read(sockaddr.as_ptr()) };
-    let ifr = unsafe { ifr_create(ifname, |ifr| ifr.ifr_ifru.ifru_netmask = tmp) };
-    socket.ioctl(libc::SIOCSIFNETMASK, ifr)?;
-    Ok(())
+// keep the interface when its last file descriptor is closed, letting
+// `owner` and the members of `group` attach to it without privileges
+fn set_persistent(file: &File, owner: Option<u32>, group: Option<u32>) -> Result<()> {
+    let check = |res: libc::c_int, what: &str| -> Result<()> {
+        if res < 0 {
+            let err = std::io::Error::last_os_error();
+            bail!("Error setting interface {}: {}", what, err);
+        }
+        Ok(())
+    };
+    let fd = file.as_raw_fd();
+    if let Some(owner) = owner {
+        let res = unsafe { libc::ioctl(fd, libc::TUNSETOWNER, owner as libc::c_ulong) };
+        check(res, "owner")?;
+    }
+    if let Some(group) = group {
+        let res = unsafe { libc::ioctl(fd, libc::TUNSETGROUP, group as libc::c_ulong) };
+        check(res, "group")?;
+    }
+    let res = unsafe { libc::ioctl(fd, libc::TUNSETPERSIST, 1 as libc::c_ulong) };
+    check(res, "persistence")
 }
 
-fn set_interface_up(socket: &Socket, ifname: &CStr) -> Result<()> {
-    let ifr = unsafe {
-        ifr_create(ifname, |ifr| {
-            ifr.ifr_ifru.ifru_flags |= libc::IFF_UP as i16;
-        })
-    };
-    socket.ioctl(libc::SIOCSIFFLAGS, ifr)?;
-    Ok(())
+// open a new file descriptor of the interface, a new queue if multi-queue
+fn open_queue(ifname: &CStr, config: &IfConfig) -> Result<File> {
+    let fd = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/net/tun")
+        .context("Cannot open /dev/net/tun")?;
+    set_interface_name(&fd, ifname, config)?;
+    Ok(fd)
 }
 
-fn set_interface_down(socket: &Socket, ifname: &CStr) -> Result<()> {
-    let ifr = unsafe {
-        ifr_create(ifname, |ifr| {
-            ifr.ifr_ifru.ifru_flags &= !(libc::IFF_UP as i16);
-        })
-    };
-    socket.ioctl(libc::SIOCSIFFLAGS, ifr)?;
-    Ok(())
+/// Packet oriented device exchanging inner packets with the tunnel
+///
+/// Every call to `recv` returns exactly one packet and every call to `send`
+/// injects exactly one packet, as happens with TUN file descriptors
+pub trait TunDevice: AsFd {
+    fn recv(&mut self, buf: &mut [u8]) -> std::io::Result<usize>;
+    fn send(&mut self, buf: &[u8]) -> std::io::Result<()>;
+}
+
+/// Interface address and its prefix length
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IfAddr {
+    pub addr: IpAddr,
+    pub netmask: u8,
+}
+
+impl IfAddr {
+    pub fn new(addr: IpAddr, netmask: u8) -> Result<Self> {
+        let max_netmask = if addr.is_ipv4() { 32 } else { 128 };
+        if netmask > max_netmask {
+            bail!("Netmask should be less than {}", max_netmask)
+        }
+        Ok(IfAddr { addr, netmask })
+    }
+
+    /// True if `other` is a distinct address of the same subnet
+    pub fn is_peer(&self, other: &IfAddr) -> bool {
+        let netmask = self.netmask as u32;
+        let same_prefix = match (self.addr, other.addr) {
+            (IpAddr::V4(local), IpAddr::V4(remote)) if netmask <= 32 => {
+                // classic netmask
+                let mask = u32::MAX.checked_shl(32 - netmask).unwrap_or(0);
+                u32::from(local) & mask == u32::from(remote) & mask
+            }
+            (IpAddr::V6(local), IpAddr::V6(remote)) if netmask <= 128 => {
+                let mask = u128::MAX.checked_shl(128 - netmask).unwrap_or(0);
+                u128::from(local) & mask == u128::from(remote) & mask
+            }
+            // different families, or invalid netmask
+            _ => false,
+        };
+        same_prefix && self.netmask == other.netmask && self.addr != other.addr
+    }
+
+    /// True if `addr` belongs to the network of the address
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        addr.is_ipv4() == self.addr.is_ipv4()
+            && IfAddr {
+                addr,
+                netmask: self.netmask,
+            }
+            .network()
+                == self.network()
+    }
+
+    /// Network of the address, host bits cleared
+    pub fn network(&self) -> IfAddr {
+        let addr = match self.addr {
+            IpAddr::V4(addr) => {
+                let mask = u32::MAX.checked_shl(32 - self.netmask as u32).unwrap_or(0);
+                Ipv4Addr::from(u32::from(addr) & mask).into()
+            }
+            IpAddr::V6(addr) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.netmask as u32)
+                    .unwrap_or(0);
+                Ipv6Addr::from(u128::from(addr) & mask).into()
+            }
+        };
+        IfAddr {
+            addr,
+            netmask: self.netmask,
+        }
+    }
+}
+
+impl fmt::Display for IfAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.netmask)
+    }
+}
+
+/// Parse ADDR/NETMASK
+impl FromStr for IfAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((addr, netmask)) = s.split_once('/') else {
+            bail!("Missing netmask in {}", s);
+        };
+        IfAddr::new(addr.parse()?, netmask.parse()?)
+    }
+}
+
+/// Ethernet address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    /// True for group addresses, broadcast included
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 1 != 0
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+/// Parse six colon separated hex bytes
+impl FromStr for MacAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut mac = [0; 6];
+        let mut bytes = s.split(':');
+        for b in mac.iter_mut() {
+            match bytes.next() {
+                Some(byte) if byte.len() == 2 => *b = u8::from_str_radix(byte, 16)?,
+                _ => bail!("Invalid MAC address {}", s),
+            }
+        }
+        if bytes.next().is_some() {
+            bail!("Invalid MAC address {}", s);
+        }
+        Ok(MacAddr(mac))
+    }
+}
+
+/// Destination and source addresses of an Ethernet frame
+pub fn ethernet_addresses(frame: &[u8]) -> Option<(MacAddr, MacAddr)> {
+    let dst = frame.get(0..6)?.try_into().ok()?;
+    let src = frame.get(6..12)?.try_into().ok()?;
+    // the EtherType must follow
+    frame.get(13)?;
+    Some((MacAddr(dst), MacAddr(src)))
+}
+
+/// Layer of the virtual interface
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IfKind {
+    // IP packets
+    #[default]
+    Tun,
+    // Ethernet frames
+    Tap,
+}
+
+impl fmt::Display for IfKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IfKind::Tun => write!(f, "TUN"),
+            IfKind::Tap => write!(f, "TAP"),
+        }
+    }
+}
+
+/// Maximum number of queues of a multi-queue interface
+pub const MAX_QUEUES: usize = 256;
+/// Size of the virtio-net header preceding every packet of an interface
+/// with offload
+pub const VNET_HDR_LEN: usize = 10;
+/// Largest packet of an interface with offload: virtio-net header, Ethernet
+/// header (TAP) then up to 64 KiB segmented by the receiver
+pub const MAX_OFFLOAD_LEN: usize = VNET_HDR_LEN + 14 + 65536;
+/// MTU of new interfaces, also assumed for remote endpoints not announcing
+/// theirs
+pub const DEFAULT_MTU: u32 = 1500;
+/// Smallest MTU accepted, the size of the datagrams every IPv4 host accepts
+pub const MIN_MTU: u32 = 576;
+/// Largest MTU of TUN and TAP interfaces
+pub const MAX_MTU: u32 = 65535;
+// Ethernet header, with room for an 802.1Q tag
+const MAX_ETHERNET_HEADER_LEN: usize = 18;
+
+/// Largest packet exchanged with an interface of `kind` and MTU `mtu`,
+/// with or without offload
+pub fn max_packet_len(kind: IfKind, mtu: u32, offload: bool) -> usize {
+    match (offload, kind) {
+        // segments are larger than the MTU
+        (true, _) => MAX_OFFLOAD_LEN,
+        (false, IfKind::Tun) => mtu as usize,
+        (false, IfKind::Tap) => MAX_ETHERNET_HEADER_LEN + mtu as usize,
+    }
+}
+
+/// Properties of the virtual interface besides its name and addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IfConfig {
+    pub kind: IfKind,
+    // (TAP) MAC address, chosen by the kernel if None
+    pub mac: Option<MacAddr>,
+    // packet queues, each with its own file descriptor
+    pub queues: usize,
+    // packets carry a virtio-net header, see `set_offload`
+    pub offload: bool,
+    pub mtu: u32,
+    // use the persistent interface as it is, see `make_persistent`
+    pub attach: bool,
+}
+
+impl IfConfig {
+    /// Size of the header preceding every packet of the interface
+    pub fn header_len(&self) -> usize {
+        if self.offload { VNET_HDR_LEN } else { 0 }
+    }
+
+    /// Largest packet read from or written to the interface
+    pub fn max_packet_len(&self) -> usize {
+        max_packet_len(self.kind, self.mtu, self.offload)
+    }
+}
+
+impl Default for IfConfig {
+    fn default() -> Self {
+        IfConfig {
+            kind: IfKind::Tun,
+            mac: None,
+            queues: 1,
+            offload: false,
+            mtu: DEFAULT_MTU,
+            attach: false,
+        }
+    }
 }
 
 pub struct Iface {
     fd: File,
+    // file descriptors of the queues after the first one
+    queues: Vec<File>,
     name: CString,
-    ip: Ipv4Addr,
-    netmask: u8,
+    // as used by netlink
+    index: u32,
+    addrs: Vec<IfAddr>,
+    // resolver configuration to restore
+    dns: Option<Box<dyn DnsBackend>>,
+    // persistent interface, left as found
+    attached: bool,
 }
 
 impl Iface {
-    pub fn new(n: &str, ip: Ipv4Addr, netmask: u8) -> Result<Self> {
-        if netmask > 32 {
-            bail!("Netmask should be less than 32")
+    /// Create the interface `n` with addresses `addrs`, at most one per
+    /// address family
+    ///
+    /// With `config.attach`, open the persistent interface `n` instead,
+    /// leaving its configuration alone.
+    pub fn new(n: &str, addrs: &[IfAddr], config: &IfConfig) -> Result<Self> {
+        check_config(n, addrs, config)?;
+        let name = CString::new(n)?;
+        let mut netlink = Netlink::new()?;
+        if config.attach {
+            // rather than failing to create it without privileges
+            let link = netlink.get_link(n)?;
+            let link_addrs = netlink.get_addresses(link.index)?;
+            check_attachable(n, &link, &link_addrs, addrs, config)?;
         }
-        if n.len() > 16 {
-            bail!("Interface name too long")
+        let fd = open_queue(&name, config)?;
+        if config.offload {
+            set_offload(&fd)?;
+        }
+        let queues = (1..config.queues)
+            .map(|_| open_queue(&name, config))
+            .collect::<Result<_>>()?;
+        let index = netlink.get_link(n)?.index;
+        if !config.attach {
+            configure(&mut netlink, index, addrs, config)?;
         }
-        let fd = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open("/dev/net/tun")?;
-
-        let name = CString::new(n)?;
-        set_interface_name(&fd, &name)?;
-        let socket = Socket::new()?;
-        set_interface_address(&socket, &name, &ip)?;
-        set_subnet_mask(&socket, &name, netmask)?;
-        set_interface_up(&socket, &name)?;
         Ok(Iface {
             fd,
+            queues,
             name,
-            ip,
-            netmask,
+            index,
+            addrs: addrs.to_vec(),
+            dns: None,
+            attached: config.attach,
         })
     }
+
+    pub fn addrs(&self) -> &[IfAddr] {
+        &self.addrs
+    }
+
+    /// File descriptor of every queue, the first one is also `as_ref`
+    pub fn queues(&self) -> impl Iterator<Item = &File> {
+        std::iter::once(&self.fd).chain(&self.queues)
+    }
+
+    /// Apply the DNS settings of the tunnel with `backend`, the previous
+    /// settings are restored when the interface is dropped
+    pub fn set_dns(&mut self, mut backend: Box<dyn DnsBackend>, config: &DnsConfig) -> Result<()> {
+        if self.dns.is_some() {
+            bail!("DNS settings already applied");
+        }
+        backend.apply(&self.name.to_string_lossy(), config)?;
+        self.dns = Some(backend);
+        Ok(())
+    }
+
+    /// Index of the interface, as used by netlink
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+// checks shared by new and persistent interfaces
+fn check_config(n: &str, addrs: &[IfAddr], config: &IfConfig) -> Result<()> {
+    if addrs.iter().filter(|ifaddr| ifaddr.addr.is_ipv4()).count() > 1
+        || addrs.iter().filter(|ifaddr| ifaddr.addr.is_ipv6()).count() > 1
+    {
+        bail!("At most one address per family is supported")
+    }
+    if n.len() > 16 {
+        bail!("Interface name too long")
+    }
+    if !(1..=MAX_QUEUES).contains(&config.queues) {
+        bail!("An interface has 1 to {} queues", MAX_QUEUES)
+    }
+    Ok(())
+}
+
+// set the MAC address, MTU and addresses of interface `index` then bring it
+// up
+fn configure(netlink: &mut Netlink, index: u32, addrs: &[IfAddr], config: &IfConfig) -> Result<()> {
+    match (config.kind, config.mac) {
+        (IfKind::Tap, Some(mac)) => netlink.set_mac(index, mac)?,
+        (IfKind::Tun, Some(_)) => bail!("Only TAP interfaces have a MAC address"),
+        (_, None) => {}
+    }
+    // first, an MTU under 1280 set later would silently remove the IPv6
+    // addresses
+    netlink.set_mtu(index, config.mtu)?;
+    for ifaddr in addrs {
+        netlink.add_address(index, ifaddr)?;
+    }
+    netlink.set_link_up(index, true)?;
+    Ok(())
+}
+
+/// Check that the persistent interface `n`, described by `link` and with
+/// global addresses `link_addrs`, carries the packets of a tunnel with
+/// addresses `addrs`
+pub fn check_attachable(
+    n: &str,
+    link: &Link,
+    link_addrs: &[IfAddr],
+    addrs: &[IfAddr],
+    config: &IfConfig,
+) -> Result<()> {
+    if link.kind.as_deref() != Some("tun") {
+        bail!("{} is not a TUN or TAP interface", n)
+    }
+    // a stale interface would not route the tunnel addresses
+    if link_addrs.len() != addrs.len() || addrs.iter().any(|addr| !link_addrs.contains(addr)) {
+        let found: Vec<String> = link_addrs.iter().map(|addr| addr.to_string()).collect();
+        let expected: Vec<String> = addrs.iter().map(|addr| addr.to_string()).collect();
+        bail!(
+            "{} has addresses [{}] instead of [{}]",
+            n,
+            found.join(", "),
+            expected.join(", ")
+        )
+    }
+    if config.mac.is_some() {
+        bail!("The MAC address of {} is already set", n)
+    }
+    // larger packets would be dropped
+    if let Some(mtu) = link.mtu.filter(|&mtu| mtu > config.mtu) {
+        bail!(
+            "{} has MTU {}, larger than the MTU {} of the tunnel",
+            n,
+            mtu,
+            config.mtu
+        )
+    }
+    Ok(())
+}
+
+/// Create the persistent interface `n`, configured as `Iface::new` does,
+/// for `owner` and the members of `group` to attach to it without
+/// privileges
+///
+/// The interface stays until `delete_persistent`, across runs of the VPN.
+pub fn make_persistent(
+    n: &str,
+    addrs: &[IfAddr],
+    config: &IfConfig,
+    owner: Option<u32>,
+    group: Option<u32>,
+) -> Result<()> {
+    check_config(n, addrs, config)?;
+    let name = CString::new(n)?;
+    let mut netlink = Netlink::new()?;
+    // opening it would attach to it
+    if netlink.get_link(n).is_ok() {
+        bail!("Interface {} already exists", n)
+    }
+    let fd = open_queue(&name, config)?;
+    let index = netlink.get_link(n)?.index;
+    let res = set_persistent(&fd, owner, group)
+        .and_then(|_| configure(&mut netlink, index, addrs, config));
+    if res.is_err() {
+        // not left half configured
+        let _ = netlink.del_link(index);
+    }
+    res
+}
+
+/// Delete the persistent interface `n`
+pub fn delete_persistent(n: &str) -> Result<()> {
+    let mut netlink = Netlink::new()?;
+    let link = netlink.get_link(n)?;
+    // not any interface
+    if link.kind.as_deref() != Some("tun") {
+        bail!("{} is not a TUN or TAP interface", n)
+    }
+    netlink.del_link(link.index)?;
+    Ok(())
 }
+
 impl AsRef<File> for Iface {
     fn as_ref(&self) -> &File {
         &self.fd
@@ -150,9 +535,43 @@ impl AsFd for Iface {
     }
 }
 
+impl TunDevice for Iface {
+    fn recv(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.fd.read(buf)
+    }
+
+    fn send(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        // https://doc.rust-lang.org/std/fs/struct.File.html#method.write_all_at-1
+        self.fd.write_all(buf)
+    }
+}
+
+/// Duplicate of the file descriptor of a TUN device, see `Iface::as_ref`
+impl TunDevice for File {
+    fn recv(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.read(buf)
+    }
+
+    fn send(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.write_all(buf)
+    }
+}
+
 impl Drop for Iface {
     fn drop(&mut self) {
-        let f = || -> Result<()> { set_interface_down(&Socket::new()?, &self.name) };
-        f().unwrap();
+        if let Some(Err(err)) = self.dns.as_mut().map(|backend| backend.restore()) {
+            eprintln!("Cannot restore DNS settings: {:#}", err);
+        }
+        if self.attached {
+            return;
+        }
+        let f = || -> Result<()> { Ok(Netlink::new()?.set_link_up(self.index, false)?) };
+        if let Err(err) = f() {
+            eprintln!(
+                "Cannot bring {} down: {:#}",
+                self.name.to_string_lossy(),
+                err
+            );
+        }
     }
 }
@@ -0,0 +1,47 @@
+// Fixtures shared by the integration tests
+//
+// Every test crate compiles its own copy and only uses part of it.
+#![allow(dead_code)]
+
+use rust_tcp_vpn::tunif::{IfAddr, TunDevice};
+use std::net::IpAddr;
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+
+/// Interface address written as ADDR/NETMASK
+pub fn ifaddr(text: &str) -> IfAddr {
+    text.parse().unwrap()
+}
+
+/// Interface address from its parts, usable in constants
+pub const fn ifaddr_of(addr: IpAddr, netmask: u8) -> IfAddr {
+    IfAddr { addr, netmask }
+}
+
+/// Write `content` to a file unique to this test process and `name`
+pub fn temp_file(name: &str, content: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rust-tcp-vpn-{}-{}", std::process::id(), name));
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+/// Datagram socket standing for a TUN device: the pair preserves packet
+/// boundaries
+pub struct FakeTun(pub UnixDatagram);
+
+impl AsFd for FakeTun {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl TunDevice for FakeTun {
+    fn recv(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.recv(buf)
+    }
+
+    fn send(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.0.send(buf).map(|_| ())
+    }
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::temp_file;
+use rust_tcp_vpn::dns::{DnsBackend, DnsConfig, ResolvConf, valid_domain};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+
+const PREVIOUS: &str = "nameserver 192.0.2.53\nsearch lan\noptions edns0\n";
+
+// resolv.conf stand-in unique to this test process
+fn resolv_conf(name: &str, content: &str) -> PathBuf {
+    temp_file(&format!("{}.resolv.conf", name), content)
+}
+
+fn config() -> DnsConfig {
+    DnsConfig {
+        servers: vec![
+            Ipv4Addr::new(10, 8, 0, 1).into(),
+            Ipv6Addr::LOCALHOST.into(),
+        ],
+        domains: vec!["vpn.example".to_string(), "corp.example".to_string()],
+    }
+}
+
+#[test]
+fn pushed_settings_come_first() {
+    let path = resolv_conf("apply", PREVIOUS);
+    let mut backend = ResolvConf::new(path.clone());
+    backend.apply("tun0", &config()).unwrap();
+    let text = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "# Generated by rust-tcp-vpn for tun0",
+            "nameserver 10.8.0.1",
+            "nameserver ::1",
+            "search vpn.example corp.example",
+            "nameserver 192.0.2.53",
+            // superseded by the pushed search list
+            "#search lan",
+            "options edns0",
+        ]
+    );
+    // applied once only
+    assert!(backend.apply("tun0", &config()).is_err());
+    backend.restore().unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), PREVIOUS);
+    // nothing left to restore
+    std::fs::write(&path, "changed\n").unwrap();
+    backend.restore().unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "changed\n");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn search_list_kept_without_pushed_domains() {
+    let path = resolv_conf("servers", PREVIOUS);
+    let mut backend = ResolvConf::new(path.clone());
+    let config = DnsConfig {
+        domains: vec![],
+        ..config()
+    };
+    backend.apply("tun0", &config).unwrap();
+    let text = std::fs::read_to_string(&path).unwrap();
+    assert!(text.ends_with(PREVIOUS));
+    backend.restore().unwrap();
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn domain_names() {
+    assert!(valid_domain("vpn.example"));
+    assert!(valid_domain("_srv.corp-1.example."));
+    assert!(!valid_domain(""));
+    assert!(!valid_domain("evil.example\nnameserver 6.6.6.6"));
+    assert!(!valid_domain("a b"));
+    assert!(!valid_domain(&"a".repeat(254)));
+}
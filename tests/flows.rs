@@ -0,0 +1,399 @@
+mod common;
+
+use common::FakeTun;
+use rust_tcp_vpn::channel::Channel;
+use rust_tcp_vpn::flows::{FlowConfig, FlowExit, handle_flow, oversized_packets};
+use rust_tcp_vpn::protocol::{
+    Capabilities, ExitReason, Frame, FrameReader, MAX_BODY_LEN, encode, write_frame,
+};
+use std::fs::File;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixDatagram;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// shrink the send buffer, so it fills up quickly (the receive buffer is
+// left alone: a small one makes loopback TCP stall on retransmissions)
+fn small_send_buffer(stream: &TcpStream) {
+    let size: libc::c_int = 16 * 1024;
+    let ret = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_SNDBUF,
+            (&size as *const libc::c_int).cast(),
+            size_of_val(&size) as libc::socklen_t,
+        )
+    };
+    assert_eq!(ret, 0);
+}
+
+// local flow running in background
+struct Flow {
+    // raw TCP stream of the remote endpoint
+    remote: TcpStream,
+    // application side of the virtual interface
+    app: UnixDatagram,
+    // write here to simulate a local signal
+    sigw: File,
+    handle: JoinHandle<anyhow::Result<FlowExit>>,
+}
+
+fn spawn_flow(config: FlowConfig) -> Flow {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let local = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (remote, _) = listener.accept().unwrap();
+    small_send_buffer(&local);
+    small_send_buffer(&remote);
+    remote
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let (tun, app) = UnixDatagram::pair().unwrap();
+    let (sig_r, sig_w) = nix::unistd::pipe().unwrap();
+    let mut sigfile: File = sig_r.into();
+    let handle = thread::spawn(move || {
+        let mut tun = FakeTun(tun);
+        let mut channel = Channel::new(local, 64);
+        handle_flow(&mut channel, &mut tun, &mut sigfile, &config)
+    });
+    Flow {
+        remote,
+        app,
+        sigw: sig_w.into(),
+        handle,
+    }
+}
+
+#[test]
+fn upload_only_traffic_reaches_remote() {
+    let Flow {
+        mut remote,
+        app,
+        mut sigw,
+        handle,
+    } = spawn_flow(FlowConfig::default());
+
+    // remote endpoint never sends anything: only local -> remote traffic
+    let mut reader = FrameReader::new(MAX_BODY_LEN);
+    for i in 0..100_u32 {
+        let pkt = vec![i as u8; 20 + i as usize];
+        app.send(&pkt).unwrap();
+        let frame = reader.read_frame(&mut remote).unwrap();
+        assert_eq!(
+            frame,
+            Frame::Data {
+                counter: i as u64 + 1,
+                payload: &pkt
+            }
+        );
+    }
+
+    // local signal terminates the flow with an exit packet
+    sigw.write_all(&[1]).unwrap();
+    assert_eq!(handle.join().unwrap().unwrap(), FlowExit::Local);
+    let frame = reader.read_frame(&mut remote).unwrap();
+    assert_eq!(frame, Frame::Exit { reason: 0 });
+}
+
+#[test]
+fn uploads_in_both_directions_at_once() {
+    const PACKETS: u64 = 2_000;
+    let Flow {
+        mut remote,
+        app,
+        mut sigw,
+        handle,
+    } = spawn_flow(FlowConfig::default());
+    app.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    // both ends upload far more than the socket buffers hold, and the remote
+    // endpoint reads nothing meanwhile
+    let upload = app.try_clone().unwrap();
+    thread::spawn(move || {
+        for _ in 0..PACKETS {
+            upload.send(&[1; 1000]).unwrap();
+        }
+    });
+    let mut remote_upload = remote.try_clone().unwrap();
+    let remote_upload = thread::spawn(move || {
+        for counter in 1..=PACKETS {
+            let frame = Frame::Data {
+                counter,
+                payload: &[2; 1000],
+            };
+            write_frame(&mut remote_upload, &frame).unwrap();
+        }
+    });
+
+    // remote -> local keeps flowing while local -> remote is stuck
+    let mut buf = [0; 2000];
+    for _ in 0..PACKETS {
+        assert_eq!(app.recv(&mut buf).unwrap(), 1000);
+        assert_eq!(buf[0], 2);
+    }
+    remote_upload.join().unwrap();
+
+    // nothing was lost on the way to the remote endpoint either
+    let mut reader = FrameReader::new(MAX_BODY_LEN);
+    for counter in 1..=PACKETS {
+        let frame = reader.read_frame(&mut remote).unwrap();
+        assert_eq!(
+            frame,
+            Frame::Data {
+                counter,
+                payload: &[1; 1000]
+            }
+        );
+    }
+
+    sigw.write_all(&[1]).unwrap();
+    assert_eq!(handle.join().unwrap().unwrap(), FlowExit::Local);
+    let frame = reader.read_frame(&mut remote).unwrap();
+    assert_eq!(frame, Frame::Exit { reason: 0 });
+}
+
+#[test]
+fn oversized_packet_is_a_protocol_violation() {
+    // keep signal pipe open: a closed pipe is a local exit
+    let Flow {
+        mut remote,
+        sigw: _sigw,
+        handle,
+        ..
+    } = spawn_flow(FlowConfig::default());
+
+    // only the header: the flow must not wait for the body
+    let mut buf = Vec::new();
+    encode(
+        &Frame::Data {
+            counter: 1,
+            payload: &[0; 100_000],
+        },
+        &mut buf,
+    );
+    remote.write_all(&buf[..8]).unwrap();
+
+    assert_eq!(handle.join().unwrap().unwrap(), FlowExit::ProtocolViolation);
+    let mut reader = FrameReader::new(MAX_BODY_LEN);
+    let frame = reader.read_frame(&mut remote).unwrap();
+    assert_eq!(
+        frame,
+        Frame::Exit {
+            reason: ExitReason::ProtocolViolation.code()
+        }
+    );
+}
+
+#[test]
+fn packets_are_bounded_by_mtu() {
+    let config = FlowConfig {
+        capabilities: Capabilities::KEEPALIVE,
+        mtu: 1400,
+        ..FlowConfig::default()
+    };
+    let Flow {
+        mut remote,
+        app,
+        sigw: _sigw,
+        handle,
+    } = spawn_flow(config);
+
+    // the remote endpoint would refuse it
+    let dropped = oversized_packets();
+    app.send(&[1; 1401]).unwrap();
+    app.send(&[2; 1400]).unwrap();
+    let mut reader = FrameReader::new(MAX_BODY_LEN);
+    let frame = reader.read_frame(&mut remote).unwrap();
+    assert_eq!(
+        frame,
+        Frame::Data {
+            counter: 1,
+            payload: &[2; 1400]
+        }
+    );
+    assert!(oversized_packets() > dropped);
+
+    let mut buf = Vec::new();
+    encode(
+        &Frame::Data {
+            counter: 1,
+            payload: &[3; 1401],
+        },
+        &mut buf,
+    );
+    remote.write_all(&buf).unwrap();
+    assert_eq!(handle.join().unwrap().unwrap(), FlowExit::ProtocolViolation);
+}
+
+#[test]
+fn handshake_frame_is_a_protocol_violation() {
+    // keep signal pipe open: a closed pipe is a local exit
+    let Flow {
+        mut remote,
+        sigw: _sigw,
+        handle,
+        ..
+    } = spawn_flow(FlowConfig::default());
+
+    let mut buf = Vec::new();
+    encode(&Frame::HandshakeStatus { status: 0 }, &mut buf);
+    remote.write_all(&buf).unwrap();
+
+    assert_eq!(handle.join().unwrap().unwrap(), FlowExit::ProtocolViolation);
+}
+
+#[test]
+fn remote_exit_reason_is_reported() {
+    let Flow {
+        mut remote,
+        app,
+        sigw: _sigw,
+        handle,
+    } = spawn_flow(FlowConfig::default());
+
+    let mut buf = Vec::new();
+    encode(
+        &Frame::Data {
+            counter: 1,
+            payload: &[1, 2, 3],
+        },
+        &mut buf,
+    );
+    encode(&Frame::Exit { reason: 1 }, &mut buf);
+    remote.write_all(&buf).unwrap();
+
+    assert_eq!(
+        handle.join().unwrap().unwrap(),
+        FlowExit::Remote(ExitReason::ProtocolViolation)
+    );
+    let mut pkt = [0; 16];
+    assert_eq!(app.recv(&mut pkt).unwrap(), 3);
+    assert_eq!(&pkt[..3], &[1, 2, 3]);
+}
+
+#[test]
+fn keepalive_detects_dead_peer() {
+    let config = FlowConfig {
+        keepalive: Some(Duration::from_millis(50)),
+        dead_peer_timeout: Some(Duration::from_millis(300)),
+        ..FlowConfig::default()
+    };
+    let Flow {
+        mut remote,
+        sigw: _sigw,
+        handle,
+        ..
+    } = spawn_flow(config);
+
+    // answer the first ping, then stay silent
+    let mut reader = FrameReader::new(MAX_BODY_LEN);
+    let Frame::Ping { timestamp } = reader.read_frame(&mut remote).unwrap() else {
+        panic!("expected Ping");
+    };
+    let mut buf = Vec::new();
+    encode(&Frame::Pong { timestamp }, &mut buf);
+    remote.write_all(&buf).unwrap();
+
+    assert_eq!(handle.join().unwrap().unwrap(), FlowExit::PeerTimeout);
+    // pings keep coming until the flow gives up
+    loop {
+        match reader.read_frame(&mut remote).unwrap() {
+            Frame::Ping { .. } => continue,
+            frame => {
+                assert_eq!(
+                    frame,
+                    Frame::Exit {
+                        reason: ExitReason::PeerTimeout.code()
+                    }
+                );
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn keepalive_disabled_without_capability() {
+    let config = FlowConfig {
+        keepalive: Some(Duration::from_millis(50)),
+        dead_peer_timeout: Some(Duration::from_millis(200)),
+        capabilities: Capabilities::empty(),
+        ..FlowConfig::default()
+    };
+    let Flow {
+        mut remote,
+        app,
+        mut sigw,
+        handle,
+    } = spawn_flow(config);
+
+    // silent remote endpoint is not considered dead, and not pinged
+    thread::sleep(Duration::from_millis(400));
+    app.send(&[1, 2, 3]).unwrap();
+    let mut reader = FrameReader::new(MAX_BODY_LEN);
+    assert_eq!(
+        reader.read_frame(&mut remote).unwrap(),
+        Frame::Data {
+            counter: 1,
+            payload: &[1, 2, 3]
+        }
+    );
+    sigw.write_all(&[1]).unwrap();
+    assert_eq!(handle.join().unwrap().unwrap(), FlowExit::Local);
+}
+
+#[test]
+fn ping_is_answered_with_pong() {
+    let Flow {
+        mut remote,
+        sigw: _sigw,
+        ..
+    } = spawn_flow(FlowConfig::default());
+
+    let mut buf = Vec::new();
+    encode(&Frame::Ping { timestamp: 1234 }, &mut buf);
+    remote.write_all(&buf).unwrap();
+    let mut reader = FrameReader::new(MAX_BODY_LEN);
+    assert_eq!(
+        reader.read_frame(&mut remote).unwrap(),
+        Frame::Pong { timestamp: 1234 }
+    );
+}
+
+#[test]
+fn replayed_frame_is_rejected() {
+    let config = FlowConfig {
+        reject_replay: true,
+        ..FlowConfig::default()
+    };
+    let Flow {
+        mut remote,
+        app,
+        sigw: _sigw,
+        handle,
+    } = spawn_flow(config);
+
+    let mut buf = Vec::new();
+    for counter in [1, 2, 1] {
+        encode(
+            &Frame::Data {
+                counter,
+                payload: &[counter as u8],
+            },
+            &mut buf,
+        );
+    }
+    remote.write_all(&buf).unwrap();
+
+    assert_eq!(handle.join().unwrap().unwrap(), FlowExit::Replay);
+    // the replayed packet is not injected
+    let mut pkt = [0; 16];
+    assert_eq!(app.recv(&mut pkt).unwrap(), 1);
+    assert_eq!(pkt[0], 1);
+    assert_eq!(app.recv(&mut pkt).unwrap(), 1);
+    assert_eq!(pkt[0], 2);
+    app.set_nonblocking(true).unwrap();
+    assert!(app.recv(&mut pkt).is_err());
+}
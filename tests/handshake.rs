@@ -0,0 +1,793 @@
+mod common;
+
+use common::{FakeTun, ifaddr_of};
+use rust_tcp_vpn::channel::Channel;
+use rust_tcp_vpn::dns::DnsConfig;
+use rust_tcp_vpn::flows::{FlowConfig, FlowExit, handle_flow};
+use rust_tcp_vpn::handshake::{
+    Endpoint, HANDSHAKE_MAX_LEN, Negotiated, Pushed, handler_client_handshake,
+    handler_server_handshake,
+};
+use rust_tcp_vpn::noise::{self, KEY_LEN, NoiseKeys};
+use rust_tcp_vpn::pool::{AddressPool, Lease};
+use rust_tcp_vpn::protocol::{Capabilities, Frame, PROTOCOL_VERSION, STATUS_UNSUPPORTED_VERSION};
+use rust_tcp_vpn::psk::{self, Psk};
+use rust_tcp_vpn::tunif::{DEFAULT_MTU, IfAddr, IfKind};
+use std::fs::File;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpListener, TcpStream};
+use std::os::unix::net::UnixDatagram;
+use std::sync::Arc;
+use std::thread;
+
+const SERVER: IfAddr = ifaddr_of(IpAddr::V4(Ipv4Addr::new(10, 8, 0, 1)), 24);
+const CLIENT: IfAddr = ifaddr_of(IpAddr::V4(Ipv4Addr::new(10, 8, 0, 2)), 24);
+const SERVER6: IfAddr = ifaddr_of(IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1)), 64);
+const CLIENT6: IfAddr = ifaddr_of(IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2)), 64);
+
+type Side = (Option<NoiseKeys>, Option<Psk>, Vec<IfAddr>);
+
+fn tun(addrs: &[IfAddr]) -> Endpoint {
+    Endpoint {
+        addrs: addrs.to_vec(),
+        kind: IfKind::Tun,
+        offload: false,
+        mtu: DEFAULT_MTU,
+        host_config: true,
+    }
+}
+
+fn connect() -> (Channel<TcpStream>, Channel<TcpStream>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    (
+        Channel::new(server, HANDSHAKE_MAX_LEN),
+        Channel::new(client, HANDSHAKE_MAX_LEN),
+    )
+}
+
+// run both sides of the handshake, return connected channels if both succeed
+fn handshake(
+    server: Side,
+    client: Side,
+) -> (Option<Channel<TcpStream>>, Option<Channel<TcpStream>>) {
+    let (mut server_channel, mut client_channel) = connect();
+    let client = thread::spawn(move || {
+        let (keys, psk, addrs) = client;
+        handler_client_handshake(
+            &mut client_channel,
+            &tun(&addrs),
+            keys.as_ref(),
+            psk.as_ref(),
+        )
+        .ok()
+        .map(|_| client_channel)
+    });
+    let (keys, psk, addrs) = server;
+    // on failure the channel is dropped, unblocking the client
+    let server = handler_server_handshake(
+        &mut server_channel,
+        &tun(&addrs),
+        None,
+        &Pushed::default(),
+        None,
+        keys.as_ref(),
+        psk.as_ref(),
+    )
+    .ok()
+    .map(|_| server_channel);
+    (server, client.join().unwrap())
+}
+
+fn succeeded(ans: (Option<Channel<TcpStream>>, Option<Channel<TcpStream>>)) -> (bool, bool) {
+    (ans.0.is_some(), ans.1.is_some())
+}
+
+fn keypair() -> ([u8; KEY_LEN], [u8; KEY_LEN]) {
+    let builder = snow::Builder::new("Noise_IK_25519_ChaChaPoly_BLAKE2s".parse().unwrap());
+    let keypair = builder.generate_keypair().unwrap();
+    (
+        keypair.private.try_into().unwrap(),
+        keypair.public.try_into().unwrap(),
+    )
+}
+
+#[test]
+fn handshake_same_subnet() {
+    let ans = handshake((None, None, vec![SERVER]), (None, None, vec![CLIENT]));
+    assert_eq!(succeeded(ans), (true, true));
+}
+
+#[test]
+fn handshake_rejects_other_subnet() {
+    let ans = handshake(
+        (None, None, vec![SERVER]),
+        (
+            None,
+            None,
+            vec![ifaddr_of(Ipv4Addr::new(10, 9, 0, 2).into(), 24)],
+        ),
+    );
+    assert_eq!(succeeded(ans), (false, false));
+}
+
+#[test]
+fn handshake_rejects_same_address() {
+    let ans = handshake((None, None, vec![SERVER]), (None, None, vec![SERVER]));
+    assert_eq!(succeeded(ans), (false, false));
+}
+
+#[test]
+fn handshake_ipv6_same_prefix() {
+    let ans = handshake((None, None, vec![SERVER6]), (None, None, vec![CLIENT6]));
+    assert_eq!(succeeded(ans), (true, true));
+}
+
+#[test]
+fn handshake_ipv6_rejects_other_prefix() {
+    let other = ifaddr_of(Ipv6Addr::new(0xfd00, 0, 0, 1, 0, 0, 0, 2).into(), 64);
+    let ans = handshake((None, None, vec![SERVER6]), (None, None, vec![other]));
+    assert_eq!(succeeded(ans), (false, false));
+}
+
+#[test]
+fn handshake_rejects_address_family_mismatch() {
+    let ans = handshake((None, None, vec![SERVER6]), (None, None, vec![CLIENT]));
+    assert_eq!(succeeded(ans), (false, false));
+}
+
+#[test]
+fn noise_handshake_encrypts_channel() {
+    let (server_private, server_public) = keypair();
+    let (client_private, client_public) = keypair();
+    let server = NoiseKeys::new(server_private, vec![client_public]);
+    let client = NoiseKeys::new(client_private, vec![server_public]);
+    let (server, client) = handshake(
+        (Some(server), None, vec![SERVER]),
+        (Some(client), None, vec![CLIENT]),
+    );
+    let (server, client) = (server.unwrap(), client.unwrap());
+    assert!(server.is_encrypted() && client.is_encrypted());
+}
+
+#[test]
+fn noise_handshake_rejects_unknown_client() {
+    let (server_private, server_public) = keypair();
+    let (client_private, _) = keypair();
+    let (_, other_public) = keypair();
+    let server = NoiseKeys::new(server_private, vec![other_public]);
+    let client = NoiseKeys::new(client_private, vec![server_public]);
+    let ans = handshake(
+        (Some(server), None, vec![SERVER]),
+        (Some(client), None, vec![CLIENT]),
+    );
+    assert_eq!(succeeded(ans), (false, false));
+}
+
+#[test]
+fn noise_handshake_rejects_cleartext_client() {
+    let (server_private, _) = keypair();
+    let (_, client_public) = keypair();
+    let server = NoiseKeys::new(server_private, vec![client_public]);
+    let ans = handshake(
+        (Some(server), None, vec![SERVER]),
+        (None, None, vec![CLIENT]),
+    );
+    assert_eq!(succeeded(ans), (false, false));
+}
+
+#[test]
+fn encrypted_flow_carries_packets() {
+    let (server_private, server_public) = keypair();
+    let (client_private, client_public) = keypair();
+    let server = NoiseKeys::new(server_private, vec![client_public]);
+    let client = NoiseKeys::new(client_private, vec![server_public]);
+    let (server, client) = handshake(
+        (Some(server), None, vec![SERVER]),
+        (Some(client), None, vec![CLIENT]),
+    );
+    let (mut server, mut client) = (server.unwrap(), client.unwrap());
+
+    let (tun, app) = UnixDatagram::pair().unwrap();
+    let (sig_r, sig_w) = nix::unistd::pipe().unwrap();
+    let mut sigfile: File = sig_r.into();
+    let mut sigw: File = sig_w.into();
+    let flow = thread::spawn(move || {
+        let mut tun = FakeTun(tun);
+        handle_flow(&mut server, &mut tun, &mut sigfile, &FlowConfig::default())
+    });
+
+    // packets of different sizes
+    let big = vec![0xab; 3000];
+    for counter in 1..=20 {
+        client
+            .send(&Frame::Data {
+                counter,
+                payload: &big[..counter as usize * 100],
+            })
+            .unwrap();
+    }
+    let mut pkt = vec![0; 4096];
+    for counter in 1..=20 {
+        assert_eq!(app.recv(&mut pkt).unwrap(), counter * 100);
+    }
+    app.send(&[1, 2, 3]).unwrap();
+    assert_eq!(
+        client.recv().unwrap(),
+        Frame::Data {
+            counter: 1,
+            payload: &[1, 2, 3]
+        }
+    );
+
+    sigw.write_all(&[1]).unwrap();
+    assert_eq!(flow.join().unwrap().unwrap(), FlowExit::Local);
+    assert_eq!(client.recv().unwrap(), Frame::Exit { reason: 0 });
+}
+
+#[test]
+fn psk_handshake_same_key() {
+    let server = Psk::new(b"correct horse battery staple").unwrap();
+    let client = Psk::new(b"correct horse battery staple").unwrap();
+    let ans = handshake(
+        (None, Some(server), vec![SERVER]),
+        (None, Some(client), vec![CLIENT]),
+    );
+    assert_eq!(succeeded(ans), (true, true));
+}
+
+#[test]
+fn psk_handshake_rejects_wrong_key() {
+    let server = Psk::new(b"correct horse battery staple").unwrap();
+    let client = Psk::new(b"incorrect horse battery staple").unwrap();
+    let ans = handshake(
+        (None, Some(server), vec![SERVER]),
+        (None, Some(client), vec![CLIENT]),
+    );
+    assert_eq!(succeeded(ans), (false, false));
+}
+
+#[test]
+fn psk_handshake_rejects_client_without_key() {
+    let server = Psk::new(b"correct horse battery staple").unwrap();
+    let ans = handshake(
+        (None, Some(server), vec![SERVER]),
+        (None, None, vec![CLIENT]),
+    );
+    assert_eq!(succeeded(ans), (false, false));
+}
+
+#[test]
+fn psk_handshake_inside_noise() {
+    let (server_private, server_public) = keypair();
+    let (client_private, client_public) = keypair();
+    let server = NoiseKeys::new(server_private, vec![client_public]);
+    let client = NoiseKeys::new(client_private, vec![server_public]);
+    let server_psk = Psk::new(b"correct horse battery staple").unwrap();
+    let client_psk = Psk::new(b"correct horse battery staple").unwrap();
+    let ans = handshake(
+        (Some(server), Some(server_psk), vec![SERVER]),
+        (Some(client), Some(client_psk), vec![CLIENT]),
+    );
+    assert_eq!(succeeded(ans), (true, true));
+}
+
+#[test]
+fn psk_rejects_short_key() {
+    assert!(Psk::new(b"short").is_err());
+}
+
+#[test]
+fn psk_proofs_cannot_be_relayed() {
+    // the relay runs its own Noise session with each endpoint, does not
+    // know the key and forwards the authentication frames between them
+    let (server_private, server_public) = keypair();
+    let (client_private, client_public) = keypair();
+    let (relay_private, relay_public) = keypair();
+    let (mut server, mut relay_up) = connect();
+    let (mut relay_down, mut client) = connect();
+    let client = thread::spawn(move || {
+        let keys = NoiseKeys::new(client_private, vec![relay_public]);
+        noise::client_handshake(&mut client, &keys)?;
+        let psk = Psk::new(b"correct horse battery staple").unwrap();
+        psk::client_auth(&mut client, &psk)
+    });
+    let relay = thread::spawn(move || {
+        let keys = NoiseKeys::new(relay_private, vec![client_public]);
+        noise::server_handshake(&mut relay_down, &keys)?;
+        let keys = NoiseKeys::new(relay_private, vec![server_public]);
+        noise::client_handshake(&mut relay_up, &keys)?;
+        // AuthChallenge, AuthResponse, AuthProof
+        let frame = relay_down.recv()?;
+        relay_up.send(&frame)?;
+        let frame = relay_up.recv()?;
+        relay_down.send(&frame)?;
+        let frame = relay_down.recv()?;
+        relay_up.send(&frame)
+    });
+    let keys = NoiseKeys::new(server_private, vec![relay_public]);
+    noise::server_handshake(&mut server, &keys).unwrap();
+    let psk = Psk::new(b"correct horse battery staple").unwrap();
+    // the client rejects the relayed response and closes the connection,
+    // the relay and then the server fail too
+    assert!(psk::server_auth(&mut server, &psk).is_err());
+    assert!(client.join().unwrap().is_err());
+    assert!(relay.join().unwrap().is_err());
+}
+
+#[test]
+fn handshake_negotiates_version_and_capabilities() {
+    let (mut server_channel, mut client_channel) = connect();
+    let client = thread::spawn(move || {
+        handler_client_handshake(&mut client_channel, &tun(&[CLIENT]), None, None).unwrap()
+    });
+    let (server, lease) = handler_server_handshake(
+        &mut server_channel,
+        &tun(&[SERVER]),
+        None,
+        &Pushed::default(),
+        None,
+        None,
+        None,
+    )
+    .unwrap();
+    let (client, setup) = client.join().unwrap();
+    assert_eq!(server, client);
+    assert_eq!(server.version, PROTOCOL_VERSION);
+    assert_eq!(
+        server.capabilities,
+        Capabilities::all() - Capabilities::ETHERNET - Capabilities::OFFLOAD
+    );
+    assert_eq!(lease.addrs(), [CLIENT]);
+    assert_eq!(setup.addrs, [CLIENT]);
+    assert_eq!(setup.pushed, Pushed::default());
+}
+
+#[test]
+fn handshake_refuses_unsupported_version() {
+    let (mut server_channel, mut client_channel) = connect();
+    let server = thread::spawn(move || {
+        handler_server_handshake(
+            &mut server_channel,
+            &tun(&[SERVER]),
+            None,
+            &Pushed::default(),
+            None,
+            None,
+            None,
+        )
+    });
+    // client from the future
+    client_channel
+        .send(&Frame::Hello {
+            min_version: PROTOCOL_VERSION + 1,
+            max_version: PROTOCOL_VERSION + 5,
+            capabilities: Capabilities::all(),
+            addrs: vec![CLIENT],
+            mtu: Some(DEFAULT_MTU),
+        })
+        .unwrap();
+    assert_eq!(
+        client_channel.recv().unwrap(),
+        Frame::HandshakeStatus {
+            status: STATUS_UNSUPPORTED_VERSION
+        }
+    );
+    let err = server.join().unwrap().unwrap_err();
+    assert!(err.to_string().contains("no common protocol version"));
+}
+
+// handshake of a `client` endpoint with a `server` endpoint
+fn endpoint_handshake(
+    server: Endpoint,
+    client: Endpoint,
+) -> (anyhow::Result<Negotiated>, anyhow::Result<Negotiated>) {
+    let (mut server_channel, mut client_channel) = connect();
+    let client = thread::spawn(move || {
+        handler_client_handshake(&mut client_channel, &client, None, None)
+            .map(|(negotiated, _)| negotiated)
+    });
+    let server = handler_server_handshake(
+        &mut server_channel,
+        &server,
+        None,
+        &Pushed::default(),
+        None,
+        None,
+        None,
+    )
+    .map(|(negotiated, _)| negotiated);
+    drop(server_channel);
+    (server, client.join().unwrap())
+}
+
+// handshake of a `client` endpoint with a `server` endpoint, both with an
+// interface of the given kind, with offload or not
+fn interface_handshake(
+    server: (IfKind, bool),
+    client: (IfKind, bool),
+) -> (anyhow::Result<Negotiated>, anyhow::Result<Negotiated>) {
+    let endpoint = |addr, (kind, offload)| Endpoint {
+        kind,
+        offload,
+        ..tun(&[addr])
+    };
+    endpoint_handshake(endpoint(SERVER, server), endpoint(CLIENT, client))
+}
+
+#[test]
+fn handshake_agrees_on_interface_kind() {
+    let (server, client) = interface_handshake((IfKind::Tap, false), (IfKind::Tap, false));
+    let (server, client) = (server.unwrap(), client.unwrap());
+    assert_eq!(server, client);
+    assert!(server.capabilities.contains(Capabilities::ETHERNET));
+
+    let (server, client) = interface_handshake((IfKind::Tun, false), (IfKind::Tap, false));
+    assert!(server.unwrap_err().to_string().contains("TAP client"));
+    assert!(client.unwrap_err().to_string().contains("not TAP"));
+    let (server, client) = interface_handshake((IfKind::Tap, false), (IfKind::Tun, false));
+    assert!(server.unwrap_err().to_string().contains("TUN client"));
+    assert!(client.unwrap_err().to_string().contains("not TUN"));
+}
+
+#[test]
+fn handshake_agrees_on_offload() {
+    let (server, client) = interface_handshake((IfKind::Tun, true), (IfKind::Tun, true));
+    let (server, client) = (server.unwrap(), client.unwrap());
+    assert_eq!(server, client);
+    assert!(server.capabilities.contains(Capabilities::OFFLOAD));
+    assert!(!server.capabilities.contains(Capabilities::ETHERNET));
+
+    let (server, client) = interface_handshake((IfKind::Tun, false), (IfKind::Tun, true));
+    let err = server.unwrap_err().to_string();
+    assert!(
+        err.contains("TUN with offload client, TUN server"),
+        "{}",
+        err
+    );
+    assert!(
+        client
+            .unwrap_err()
+            .to_string()
+            .contains("not TUN with offload")
+    );
+    let (server, client) = interface_handshake((IfKind::Tap, true), (IfKind::Tap, false));
+    assert!(server.is_err() && client.is_err());
+}
+
+#[test]
+fn handshake_agrees_on_smallest_mtu() {
+    let endpoint = |addr, mtu| Endpoint {
+        mtu,
+        ..tun(&[addr])
+    };
+    for (server_mtu, client_mtu) in [(1400, 9000), (9000, 1280), (DEFAULT_MTU, DEFAULT_MTU)] {
+        let (server, client) =
+            endpoint_handshake(endpoint(SERVER, server_mtu), endpoint(CLIENT, client_mtu));
+        let (server, client) = (server.unwrap(), client.unwrap());
+        assert_eq!(server, client);
+        assert_eq!(server.mtu, server_mtu.min(client_mtu));
+    }
+}
+
+#[test]
+fn mtu_of_older_clients_is_the_default() {
+    let (mut server_channel, mut client_channel) = connect();
+    let server = thread::spawn(move || {
+        let local = Endpoint {
+            mtu: 9000,
+            ..tun(&[SERVER])
+        };
+        handler_server_handshake(
+            &mut server_channel,
+            &local,
+            None,
+            &Pushed::default(),
+            None,
+            None,
+            None,
+        )
+    });
+    client_channel
+        .send(&Frame::Hello {
+            min_version: PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::KEEPALIVE,
+            addrs: vec![CLIENT],
+            mtu: None,
+        })
+        .unwrap();
+    let Frame::HelloReply { mtu, .. } = client_channel.recv().unwrap() else {
+        panic!("HelloReply expected");
+    };
+    assert_eq!(mtu, None);
+    client_channel
+        .send(&Frame::HandshakeStatus { status: 0 })
+        .unwrap();
+    let (negotiated, _) = server.join().unwrap().unwrap();
+    assert_eq!(negotiated.mtu, DEFAULT_MTU);
+}
+
+#[test]
+fn handshake_dual_stack() {
+    let ans = handshake(
+        (None, None, vec![SERVER, SERVER6]),
+        (None, None, vec![CLIENT6, CLIENT]),
+    );
+    assert_eq!(succeeded(ans), (true, true));
+}
+
+#[test]
+fn handshake_rejects_missing_family() {
+    let ans = handshake(
+        (None, None, vec![SERVER, SERVER6]),
+        (None, None, vec![CLIENT]),
+    );
+    assert_eq!(succeeded(ans), (false, false));
+    let ans = handshake(
+        (None, None, vec![SERVER]),
+        (None, None, vec![CLIENT, CLIENT6]),
+    );
+    assert_eq!(succeeded(ans), (false, false));
+}
+
+#[test]
+fn handshake_rejects_one_bad_address() {
+    let other = ifaddr_of(Ipv6Addr::new(0xfd00, 0, 0, 1, 0, 0, 0, 2).into(), 64);
+    let ans = handshake(
+        (None, None, vec![SERVER, SERVER6]),
+        (None, None, vec![CLIENT, other]),
+    );
+    assert_eq!(succeeded(ans), (false, false));
+}
+
+#[test]
+fn handshake_rejects_duplicate_family() {
+    let (mut server_channel, mut client_channel) = connect();
+    let server = thread::spawn(move || {
+        handler_server_handshake(
+            &mut server_channel,
+            &tun(&[SERVER]),
+            None,
+            &Pushed::default(),
+            None,
+            None,
+            None,
+        )
+    });
+    let other = ifaddr_of(Ipv4Addr::new(10, 8, 0, 3).into(), 24);
+    client_channel
+        .send(&Frame::Hello {
+            min_version: PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::all() - Capabilities::ETHERNET - Capabilities::OFFLOAD,
+            addrs: vec![CLIENT, other],
+            mtu: Some(DEFAULT_MTU),
+        })
+        .unwrap();
+    let err = server.join().unwrap().unwrap_err();
+    assert!(err.to_string().contains("several remote"));
+}
+
+const ROUTES: [IfAddr; 2] = [
+    ifaddr_of(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 0)), 16),
+    ifaddr_of(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+];
+
+fn pushed() -> Pushed {
+    Pushed {
+        routes: ROUTES.to_vec(),
+        dns: DnsConfig {
+            servers: vec![
+                Ipv4Addr::new(10, 8, 0, 1).into(),
+                Ipv6Addr::LOCALHOST.into(),
+            ],
+            domains: vec!["vpn.example".to_string(), "example.org".to_string()],
+        },
+    }
+}
+
+#[test]
+fn server_pushes_configuration() {
+    let (mut server_channel, mut client_channel) = connect();
+    let client = thread::spawn(move || {
+        handler_client_handshake(&mut client_channel, &tun(&[CLIENT]), None, None).unwrap()
+    });
+    handler_server_handshake(
+        &mut server_channel,
+        &tun(&[SERVER]),
+        None,
+        &pushed(),
+        None,
+        None,
+        None,
+    )
+    .unwrap();
+    let (_, setup) = client.join().unwrap();
+    assert_eq!(setup.pushed, pushed());
+}
+
+#[test]
+fn attached_client_is_not_pushed_configuration() {
+    let (mut server_channel, mut client_channel) = connect();
+    let client = thread::spawn(move || {
+        let local = Endpoint {
+            host_config: false,
+            ..tun(&[CLIENT])
+        };
+        handler_client_handshake(&mut client_channel, &local, None, None).unwrap()
+    });
+    handler_server_handshake(
+        &mut server_channel,
+        &tun(&[SERVER]),
+        None,
+        &pushed(),
+        None,
+        None,
+        None,
+    )
+    .unwrap();
+    let (negotiated, setup) = client.join().unwrap();
+    assert!(!negotiated.capabilities.contains(Capabilities::ROUTES));
+    assert!(!negotiated.capabilities.contains(Capabilities::DNS));
+    assert_eq!(setup.pushed, Pushed::default());
+}
+
+#[test]
+fn pushed_configuration_needs_capability() {
+    let (mut server_channel, mut client_channel) = connect();
+    let server = thread::spawn(move || {
+        handler_server_handshake(
+            &mut server_channel,
+            &tun(&[SERVER]),
+            None,
+            &pushed(),
+            None,
+            None,
+            None,
+        )
+    });
+    // client installing routes only, not announcing its MTU
+    client_channel
+        .send(&Frame::Hello {
+            min_version: PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::KEEPALIVE | Capabilities::ROUTES,
+            addrs: vec![CLIENT],
+            mtu: None,
+        })
+        .unwrap();
+    let Frame::HelloReply {
+        routes,
+        dns,
+        domains,
+        mtu,
+        ..
+    } = client_channel.recv().unwrap()
+    else {
+        panic!("HelloReply expected");
+    };
+    assert_eq!(routes, ROUTES);
+    assert!(dns.is_empty() && domains.is_empty());
+    assert_eq!(mtu, None);
+    client_channel
+        .send(&Frame::HandshakeStatus { status: 0 })
+        .unwrap();
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn client_rejects_route_with_host_bits() {
+    let (mut server_channel, mut client_channel) = connect();
+    let client = thread::spawn(move || {
+        handler_client_handshake(&mut client_channel, &tun(&[CLIENT]), None, None).map(|_| ())
+    });
+    let bad = Pushed {
+        routes: vec![ifaddr_of(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), 16)],
+        ..Pushed::default()
+    };
+    let server = handler_server_handshake(
+        &mut server_channel,
+        &tun(&[SERVER]),
+        None,
+        &bad,
+        None,
+        None,
+        None,
+    );
+    assert!(server.is_err());
+    let err = client.join().unwrap().unwrap_err();
+    assert!(err.to_string().contains("not a network"));
+}
+
+#[test]
+fn ifaddr_network() {
+    assert_eq!(
+        CLIENT.network(),
+        ifaddr_of(IpAddr::V4(Ipv4Addr::new(10, 8, 0, 0)), 24)
+    );
+    assert_eq!(
+        CLIENT6.network(),
+        ifaddr_of(IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0)), 64)
+    );
+    assert_eq!(ROUTES[1].network(), ROUTES[1]);
+}
+
+#[test]
+fn ifaddr_parsing() {
+    let addr: IfAddr = "fd00::2/64".parse().unwrap();
+    assert_eq!(addr, CLIENT6);
+    assert_eq!(CLIENT.to_string(), "10.8.0.2/24");
+    assert!("10.8.0.2".parse::<IfAddr>().is_err());
+    assert!("10.8.0.2/33".parse::<IfAddr>().is_err());
+    assert!("fd00::2/129".parse::<IfAddr>().is_err());
+}
+
+// handshake of a client with addresses `addrs` with a server assigning them
+// from `pool`
+fn pooled_handshake(
+    pool: &Arc<AddressPool>,
+    server: &[IfAddr],
+    addrs: Vec<IfAddr>,
+) -> (anyhow::Result<Lease>, anyhow::Result<Vec<IfAddr>>) {
+    let (mut server_channel, mut client_channel) = connect();
+    let client = thread::spawn(move || {
+        handler_client_handshake(&mut client_channel, &tun(&addrs), None, None)
+            .map(|(_, setup)| setup.addrs)
+    });
+    let server = handler_server_handshake(
+        &mut server_channel,
+        &tun(server),
+        Some(pool),
+        &Pushed::default(),
+        None,
+        None,
+        None,
+    )
+    .map(|(_, lease)| lease);
+    // unblock the client on failure
+    drop(server_channel);
+    (server, client.join().unwrap())
+}
+
+#[test]
+fn server_assigns_addresses() {
+    let pool = ifaddr_of(Ipv4Addr::new(10, 8, 0, 0).into(), 24);
+    let pool6 = ifaddr_of(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0).into(), 64);
+    let pool = AddressPool::new(&[pool, pool6], &[SERVER, SERVER6], None).unwrap();
+    let (lease, assigned) = pooled_handshake(&pool, &[SERVER, SERVER6], vec![]);
+    let (lease, assigned) = (lease.unwrap(), assigned.unwrap());
+    // the server address is skipped
+    assert_eq!(assigned, [CLIENT, CLIENT6]);
+    assert_eq!(lease.addrs(), assigned);
+    // next client gets the next addresses
+    let (_, assigned) = pooled_handshake(&pool, &[SERVER, SERVER6], vec![]);
+    let next = ifaddr_of(Ipv4Addr::new(10, 8, 0, 3).into(), 24);
+    let next6 = ifaddr_of(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 3).into(), 64);
+    assert_eq!(assigned.unwrap(), [next, next6]);
+}
+
+#[test]
+fn pool_detects_conflicts() {
+    let pool = ifaddr_of(Ipv4Addr::new(10, 8, 0, 0).into(), 24);
+    let pool = AddressPool::new(&[pool], &[SERVER], None).unwrap();
+    let (lease, _) = pooled_handshake(&pool, &[SERVER], vec![CLIENT]);
+    let lease = lease.unwrap();
+    // chosen address already in use
+    let (server, client) = pooled_handshake(&pool, &[SERVER], vec![CLIENT]);
+    assert!(server.is_err() && client.is_err());
+    // never assigned while in use
+    let (_, assigned) = pooled_handshake(&pool, &[SERVER], vec![]);
+    assert_ne!(assigned.unwrap(), [CLIENT]);
+    // released when the client goes away
+    drop(lease);
+    let (_, assigned) = pooled_handshake(&pool, &[SERVER], vec![]);
+    assert_eq!(assigned.unwrap(), [CLIENT]);
+}
+
+#[test]
+fn handshake_without_pool_refuses_address_request() {
+    let ans = handshake((None, None, vec![SERVER]), (None, None, vec![]));
+    assert_eq!(succeeded(ans), (false, false));
+}
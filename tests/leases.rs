@@ -0,0 +1,137 @@
+mod common;
+
+use common::{ifaddr, temp_file};
+use rust_tcp_vpn::leases::{ClientId, LeaseFile, write_file};
+use rust_tcp_vpn::pool::AddressPool;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+const ALICE: ClientId = ClientId::Noise([1; 32]);
+const BOB: ClientId = ClientId::Tls([2; 32]);
+const DAY: Option<Duration> = Some(Duration::from_secs(86400));
+
+// lease file unique to this test process, holding `content`
+fn lease_path(name: &str, content: &str) -> PathBuf {
+    temp_file(&format!("{}.leases", name), content)
+}
+
+fn pool(path: &Path, expiry: Option<Duration>) -> Arc<AddressPool> {
+    let leases = LeaseFile::load(path.to_path_buf(), expiry).unwrap();
+    AddressPool::new(
+        &[ifaddr("10.8.0.0/24")],
+        &[ifaddr("10.8.0.1/24")],
+        Some(leases),
+    )
+    .unwrap()
+}
+
+#[test]
+fn identity_text() {
+    let text = format!("noise:{}", "01".repeat(32));
+    assert_eq!(ALICE.to_string(), text);
+    assert_eq!(text.parse::<ClientId>().unwrap(), ALICE);
+    assert_eq!(BOB.to_string().parse::<ClientId>().unwrap(), BOB);
+    assert!("ssh:0101".parse::<ClientId>().is_err());
+    assert!("noise:0101".parse::<ClientId>().is_err());
+}
+
+#[test]
+fn clients_get_their_address_back() {
+    let path = lease_path("back", "");
+    let pool = pool(&path, DAY);
+    let alice = pool.assign(&[], Some(ALICE)).unwrap();
+    assert_eq!(alice.addrs(), [ifaddr("10.8.0.2/24")]);
+    drop(alice);
+    // the address of the offline client is not given to others
+    let bob = pool.assign(&[], Some(BOB)).unwrap();
+    assert_eq!(bob.addrs(), [ifaddr("10.8.0.3/24")]);
+    let anonymous = pool.assign(&[], None).unwrap();
+    assert_eq!(anonymous.addrs(), [ifaddr("10.8.0.4/24")]);
+    let alice = pool.assign(&[], Some(ALICE)).unwrap();
+    assert_eq!(alice.addrs(), [ifaddr("10.8.0.2/24")]);
+    drop(alice);
+    let leases = pool.leases();
+    assert_eq!(leases.len(), 2);
+    assert_eq!(leases[0].0, ALICE);
+    assert_eq!(leases[0].1.addrs, [ifaddr("10.8.0.2/24").addr]);
+
+    // leases survive the server
+    drop((bob, anonymous, pool));
+    let pool = self::pool(&path, DAY);
+    let bob = pool.assign(&[], Some(BOB)).unwrap();
+    assert_eq!(bob.addrs(), [ifaddr("10.8.0.3/24")]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn leased_address_in_use_is_replaced() {
+    // both leases hold the same address, e.g. after editing the file
+    let now = rust_tcp_vpn::leases::now();
+    let content = format!("{} 10.8.0.2 {}\n{} 10.8.0.2 {}\n", ALICE, now, BOB, now);
+    let path = lease_path("replaced", &content);
+    let pool = pool(&path, DAY);
+    let _bob = pool.assign(&[], Some(BOB)).unwrap();
+    let alice = pool.assign(&[], Some(ALICE)).unwrap();
+    assert_eq!(alice.addrs(), [ifaddr("10.8.0.3/24")]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn leased_address_cannot_be_requested() {
+    let path = lease_path("requested", "");
+    let pool = pool(&path, DAY);
+    drop(pool.assign(&[], Some(ALICE)).unwrap());
+    // Alice is away, her address is kept for her
+    assert!(pool.assign(&[ifaddr("10.8.0.2/24")], None).is_err());
+    assert!(pool.assign(&[ifaddr("10.8.0.2/24")], Some(BOB)).is_err());
+    let alice = pool.assign(&[ifaddr("10.8.0.2/24")], Some(ALICE)).unwrap();
+    assert_eq!(alice.addrs(), [ifaddr("10.8.0.2/24")]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn stale_leases_expire() {
+    // last seen at the epoch
+    let content = format!("# old lease\n{} 10.8.0.2 0\n", ALICE);
+    let path = lease_path("expire", &content);
+    let pool = pool(&path, None);
+    assert_eq!(
+        pool.assign(&[], None).unwrap().addrs(),
+        [ifaddr("10.8.0.3/24")]
+    );
+    drop(pool);
+    let pool = self::pool(&path, DAY);
+    assert_eq!(
+        pool.assign(&[], None).unwrap().addrs(),
+        [ifaddr("10.8.0.2/24")]
+    );
+    assert!(pool.leases().is_empty());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn invalid_lease_file() {
+    let path = lease_path("invalid", "noise:01 10.8.0.2 0\n");
+    assert!(LeaseFile::load(path.clone(), None).is_err());
+    std::fs::write(&path, format!("{} 10.8.0.2\n", ALICE)).unwrap();
+    assert!(LeaseFile::load(path.clone(), None).is_err());
+    std::fs::remove_file(&path).unwrap();
+    // no lease yet
+    assert!(LeaseFile::load(path, None).is_ok());
+}
+
+#[test]
+fn files_sharing_a_stem_are_written_apart() {
+    let leases = lease_path("stem", "");
+    let status = leases.with_extension("status");
+    write_file(&status, "client\n").unwrap();
+    let file = LeaseFile::load(leases.clone(), DAY).unwrap();
+    file.save().unwrap();
+    // temporary files named after the whole file name
+    assert!(!leases.with_extension("tmp").exists());
+    assert_eq!(std::fs::read_to_string(&status).unwrap(), "client\n");
+    assert_eq!(std::fs::read_to_string(&leases).unwrap(), "");
+    std::fs::remove_file(leases).unwrap();
+    std::fs::remove_file(status).unwrap();
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use common::ifaddr;
+use rust_tcp_vpn::client::{full_tunnel_routes, split_default_routes};
+use rust_tcp_vpn::netlink::{Netlink, NetlinkError, Operation, Route};
+use std::net::IpAddr;
+
+#[test]
+fn full_tunnel_covers_every_address() {
+    let routes = full_tunnel_routes(&[ifaddr("10.8.0.2/24")]);
+    assert_eq!(routes, [ifaddr("0.0.0.0/1"), ifaddr("128.0.0.0/1")]);
+    let routes = full_tunnel_routes(&[ifaddr("10.8.0.2/24"), ifaddr("fd00::2/64")]);
+    assert_eq!(routes.len(), 4);
+    for addr in ["1.2.3.4", "200.1.1.1", "2001:db8::1", "fe80::1"] {
+        let addr: IpAddr = addr.parse().unwrap();
+        assert_eq!(
+            routes.iter().filter(|route| route.contains(addr)).count(),
+            1,
+            "{}",
+            addr
+        );
+    }
+    assert!(full_tunnel_routes(&[]).is_empty());
+}
+
+#[test]
+fn default_routes_are_split() {
+    let pushed = [
+        ifaddr("192.168.10.0/24"),
+        ifaddr("0.0.0.0/0"),
+        ifaddr("0.0.0.0/1"),
+        ifaddr("::/0"),
+    ];
+    assert_eq!(
+        split_default_routes(&pushed),
+        [
+            ifaddr("192.168.10.0/24"),
+            ifaddr("0.0.0.0/1"),
+            ifaddr("128.0.0.0/1"),
+            ifaddr("::/1"),
+            ifaddr("8000::/1"),
+        ]
+    );
+}
+
+#[test]
+fn ifaddr_contains() {
+    let network = ifaddr("192.168.1.7/24");
+    assert!(network.contains("192.168.1.200".parse().unwrap()));
+    assert!(!network.contains("192.168.2.1".parse().unwrap()));
+    assert!(!network.contains("::ffff:192.168.1.1".parse().unwrap()));
+    assert!(ifaddr("::/0").contains("2001:db8::1".parse().unwrap()));
+}
+
+#[test]
+fn loopback_route_lookup() {
+    // lookups need no privilege
+    let mut netlink = Netlink::new().unwrap();
+    let lookup = netlink.get_route("127.0.0.1".parse().unwrap()).unwrap();
+    assert_eq!(lookup.kind, libc::RTN_LOCAL);
+    assert_eq!(lookup.gateway, None);
+    assert!(lookup.ifindex.is_some());
+}
+
+#[test]
+fn link_lookup() {
+    let mut netlink = Netlink::new().unwrap();
+    let link = netlink.get_link("lo").unwrap();
+    let lookup = netlink.get_route("127.0.0.1".parse().unwrap()).unwrap();
+    assert_eq!(lookup.ifindex, Some(link.index));
+    assert!(link.mtu.is_some());
+    // not created by a driver
+    assert_eq!(link.kind, None);
+    // loopback addresses have host scope
+    assert!(netlink.get_addresses(link.index).unwrap().is_empty());
+    let err = netlink.get_link("nosuchif0").unwrap_err();
+    assert_eq!(err.operation, Operation::GetLink("nosuchif0".to_string()));
+    assert_eq!(err.raw_os_error(), Some(libc::ENODEV));
+}
+
+#[test]
+fn errors_name_the_operation() {
+    let route = Route {
+        dst: ifaddr("10.9.0.0/24"),
+        gateway: None,
+        ifindex: 3,
+    };
+    let err = NetlinkError {
+        operation: Operation::AddRoute(route),
+        source: std::io::Error::from_raw_os_error(libc::EEXIST),
+    };
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    assert!(
+        err.to_string()
+            .starts_with("Error adding route 10.9.0.0/24: ")
+    );
+    let operation = Operation::AddAddress {
+        ifindex: 3,
+        addr: ifaddr("fd00::2/64"),
+    };
+    assert_eq!(
+        operation.to_string(),
+        "adding address fd00::2/64 to interface 3"
+    );
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::ifaddr;
+use rust_tcp_vpn::parsing::{Args, Mode, parse_arg_from};
+use rust_tcp_vpn::tunif::IfKind;
+
+fn parse(args: &str) -> anyhow::Result<Args> {
+    parse_arg_from(std::iter::once("rust-tcp-vpn").chain(args.split_whitespace()))
+}
+
+#[test]
+fn mktun_resolves_owner() {
+    let args = parse("mktun --ifname ptun0 --ifaddr 10.8.0.2/24 --mtu 1400 --user 1000").unwrap();
+    let Mode::MakeTun(owner) = args.mode else {
+        panic!("not mktun");
+    };
+    assert_eq!((owner.user, owner.group), (Some(1000), None));
+    assert_eq!(args.interface.ifname, "ptun0");
+    assert_eq!(args.interface.addrs, [ifaddr("10.8.0.2/24")]);
+    assert_eq!(args.interface.config.mtu, 1400);
+    assert_eq!(args.interface.config.queues, 1);
+
+    // names are looked up
+    let args = parse("mktun --tap --user root --group root --multi-queue").unwrap();
+    let Mode::MakeTun(owner) = args.mode else {
+        panic!("not mktun");
+    };
+    assert_eq!((owner.user, owner.group), (Some(0), Some(0)));
+    assert_eq!(args.interface.ifname, "tap0");
+    assert_eq!(args.interface.config.kind, IfKind::Tap);
+    assert!(args.interface.config.queues > 1);
+}
+
+#[test]
+fn mktun_needs_an_owner() {
+    assert!(parse("mktun --ifname ptun0").is_err());
+    assert!(parse("mktun --user no-such-user-here").is_err());
+    assert!(parse("mktun --group no-such-group-here").is_err());
+    assert!(parse("mktun --user 1000 --mtu 100").is_err());
+    // TUN interfaces have no MAC address
+    assert!(parse("mktun --user 1000 --mac 02:00:00:00:00:01").is_err());
+    // the VPN options go without subcommand
+    assert!(parse("--host 10.0.0.1 --port 1789 mktun --user 1000").is_err());
+    assert!(parse("mktun --user 1000 --server").is_err());
+}
+
+#[test]
+fn rmtun_names_the_interface() {
+    let args = parse("rmtun --ifname ptun0").unwrap();
+    assert!(matches!(args.mode, Mode::DeleteTun));
+    assert_eq!(args.interface.ifname, "ptun0");
+    assert!(parse("rmtun").is_err());
+}
+
+#[test]
+fn attach_options() {
+    let args = parse("--host 10.0.0.1 --port 1789 --ifaddr 10.8.0.2/24 --attach").unwrap();
+    assert!(matches!(args.mode, Mode::Client(_)));
+    assert!(args.interface.config.attach);
+    let args = parse("--host 10.0.0.1 --port 1789 --ifaddr 10.8.0.2/24").unwrap();
+    assert!(!args.interface.config.attach);
+    // the addresses of the interface are checked against --ifaddr
+    assert!(parse("--host 10.0.0.1 --port 1789 --attach").is_err());
+    // the MAC address is already set
+    assert!(parse("--host 10.0.0.1 --port 1789 --ifaddr 10.8.0.2/24 --tap --mac 02:00:00:00:00:01 --attach").is_err());
+    // routes cannot be changed without privileges
+    assert!(
+        parse("--host 10.0.0.1 --port 1789 --ifaddr 10.8.0.2/24 --full-tunnel --attach").is_err()
+    );
+    // neither subcommand nor host
+    assert!(parse("--ifaddr 10.8.0.2/24 --attach").is_err());
+}
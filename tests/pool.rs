@@ -0,0 +1,47 @@
+mod common;
+
+use common::ifaddr;
+use rust_tcp_vpn::pool::AddressPool;
+
+#[test]
+fn pool_must_match_server_subnets() {
+    let server = [ifaddr("10.8.0.1/24")];
+    assert!(AddressPool::new(&[ifaddr("10.8.0.128/25")], &server, None).is_ok());
+    // other subnet, larger than the subnet
+    assert!(AddressPool::new(&[ifaddr("10.9.0.0/24")], &server, None).is_err());
+    assert!(AddressPool::new(&[ifaddr("10.8.0.0/16")], &server, None).is_err());
+    // no server address of the family
+    assert!(AddressPool::new(&[ifaddr("fd00::/64")], &server, None).is_err());
+    // two pools of the same family
+    let pools = [ifaddr("10.8.0.0/25"), ifaddr("10.8.0.128/25")];
+    assert!(AddressPool::new(&pools, &server, None).is_err());
+    // the IPv6 address of the server has no pool
+    let server = [ifaddr("10.8.0.1/24"), ifaddr("fd00::1/64")];
+    assert!(AddressPool::new(&[ifaddr("10.8.0.0/24")], &server, None).is_err());
+}
+
+#[test]
+fn pool_exhaustion() {
+    let pool = AddressPool::new(&[ifaddr("10.8.0.4/30")], &[ifaddr("10.8.0.1/24")], None).unwrap();
+    // network and broadcast addresses are left out
+    let first = pool.assign(&[], None).unwrap();
+    assert_eq!(first.addrs(), [ifaddr("10.8.0.5/24")]);
+    let second = pool.assign(&[], None).unwrap();
+    assert_eq!(second.addrs(), [ifaddr("10.8.0.6/24")]);
+    assert!(pool.assign(&[], None).is_err());
+    drop(first);
+    assert_eq!(
+        pool.assign(&[], None).unwrap().addrs(),
+        [ifaddr("10.8.0.5/24")]
+    );
+}
+
+#[test]
+fn addresses_outside_pool_are_not_tracked() {
+    let pool =
+        AddressPool::new(&[ifaddr("10.8.0.128/25")], &[ifaddr("10.8.0.1/24")], None).unwrap();
+    let _lease = pool.assign(&[ifaddr("10.8.0.2/24")], None).unwrap();
+    assert!(pool.assign(&[ifaddr("10.8.0.2/24")], None).is_ok());
+    let _lease = pool.assign(&[ifaddr("10.8.0.200/24")], None).unwrap();
+    assert!(pool.assign(&[ifaddr("10.8.0.200/24")], None).is_err());
+}
@@ -0,0 +1,373 @@
+mod common;
+
+use common::ifaddr_of;
+use rust_tcp_vpn::protocol::{
+    Capabilities, DecodeError, Frame, FrameReader, HEADER_LEN, MAX_BODY_LEN, decode, encode,
+};
+use std::io::Cursor;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+fn roundtrip(frame: Frame) {
+    let mut buf = Vec::new();
+    encode(&frame, &mut buf);
+    // every prefix is incomplete
+    for i in 0..buf.len() {
+        assert_eq!(decode(&buf[..i], MAX_BODY_LEN), Ok(None));
+    }
+    assert_eq!(decode(&buf, MAX_BODY_LEN), Ok(Some((frame, buf.len()))));
+}
+
+#[test]
+fn frames_roundtrip() {
+    roundtrip(Frame::Data {
+        counter: 42,
+        payload: &[1, 2, 3, 4, 5],
+    });
+    roundtrip(Frame::Data {
+        counter: u64::MAX,
+        payload: &[],
+    });
+    roundtrip(Frame::Exit { reason: 0 });
+    roundtrip(Frame::Ping { timestamp: 7 });
+    roundtrip(Frame::Pong { timestamp: 7 });
+    roundtrip(Frame::Hello {
+        min_version: 1,
+        max_version: 3,
+        capabilities: Capabilities::KEEPALIVE,
+        addrs: vec![ifaddr_of(Ipv4Addr::new(10, 0, 0, 2).into(), 24)],
+        mtu: None,
+    });
+    roundtrip(Frame::HelloReply {
+        version: 2,
+        capabilities: Capabilities::empty(),
+        addrs: vec![ifaddr_of(Ipv4Addr::new(10, 0, 0, 1).into(), 24)],
+        assigned: vec![],
+        routes: vec![],
+        dns: vec![],
+        domains: vec![],
+        mtu: None,
+    });
+    roundtrip(Frame::Hello {
+        min_version: 1,
+        max_version: 1,
+        capabilities: Capabilities::all(),
+        addrs: vec![ifaddr_of(
+            Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2).into(),
+            64,
+        )],
+        mtu: Some(1500),
+    });
+    roundtrip(Frame::HelloReply {
+        version: 1,
+        capabilities: Capabilities::all(),
+        addrs: vec![ifaddr_of(
+            Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1).into(),
+            64,
+        )],
+        assigned: vec![],
+        routes: vec![],
+        dns: vec![],
+        domains: vec![],
+        mtu: None,
+    });
+    roundtrip(Frame::Hello {
+        min_version: 1,
+        max_version: 1,
+        capabilities: Capabilities::all(),
+        addrs: vec![
+            ifaddr_of(Ipv4Addr::new(10, 0, 0, 2).into(), 24),
+            ifaddr_of(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2).into(), 64),
+        ],
+        mtu: Some(9000),
+    });
+    roundtrip(Frame::HelloReply {
+        version: 1,
+        capabilities: Capabilities::all(),
+        addrs: vec![ifaddr_of(Ipv4Addr::new(10, 0, 0, 1).into(), 24)],
+        assigned: vec![ifaddr_of(Ipv4Addr::new(10, 0, 0, 2).into(), 24)],
+        routes: vec![],
+        dns: vec![],
+        domains: vec![],
+        mtu: None,
+    });
+    // routes without assigned addresses
+    roundtrip(Frame::HelloReply {
+        version: 1,
+        capabilities: Capabilities::all(),
+        addrs: vec![ifaddr_of(Ipv4Addr::new(10, 0, 0, 1).into(), 24)],
+        assigned: vec![],
+        routes: vec![
+            ifaddr_of(Ipv4Addr::new(192, 168, 0, 0).into(), 16),
+            ifaddr_of(Ipv6Addr::UNSPECIFIED.into(), 0),
+        ],
+        dns: vec![],
+        domains: vec![],
+        mtu: None,
+    });
+    // DNS settings only
+    roundtrip(Frame::HelloReply {
+        version: 1,
+        capabilities: Capabilities::all(),
+        addrs: vec![ifaddr_of(Ipv4Addr::new(10, 0, 0, 1).into(), 24)],
+        assigned: vec![],
+        routes: vec![],
+        dns: vec![
+            Ipv4Addr::new(10, 0, 0, 1).into(),
+            Ipv6Addr::LOCALHOST.into(),
+        ],
+        domains: vec!["vpn.example".to_string()],
+        mtu: None,
+    });
+    // MTU only, every list is encoded before it
+    roundtrip(Frame::HelloReply {
+        version: 1,
+        capabilities: Capabilities::all(),
+        addrs: vec![ifaddr_of(Ipv4Addr::new(10, 0, 0, 1).into(), 24)],
+        assigned: vec![],
+        routes: vec![],
+        dns: vec![],
+        domains: vec![],
+        mtu: Some(1400),
+    });
+    roundtrip(Frame::HandshakeStatus { status: 0 });
+    roundtrip(Frame::Noise { message: &[3; 48] });
+    roundtrip(Frame::AuthChallenge { nonce: &[5; 32] });
+    roundtrip(Frame::AuthResponse {
+        nonce: &[6; 32],
+        mac: &[7; 32],
+    });
+    roundtrip(Frame::AuthProof { mac: &[8; 32] });
+    roundtrip(Frame::Sealed {
+        ciphertext: &[4; 100],
+    });
+}
+
+#[test]
+fn rejects_oversized_body_from_header() {
+    let mut buf = Vec::new();
+    encode(
+        &Frame::Data {
+            counter: 1,
+            payload: &[0; 100],
+        },
+        &mut buf,
+    );
+    // only the header is needed to reject the frame
+    assert_eq!(
+        decode(&buf[..HEADER_LEN], 64),
+        Err(DecodeError::TooLong { len: 108, max: 64 })
+    );
+}
+
+#[test]
+fn rejects_malformed_frames() {
+    let mut unknown = 0xdead_u32.to_be_bytes().to_vec();
+    unknown.extend_from_slice(&0_u32.to_be_bytes());
+    assert_eq!(
+        decode(&unknown, MAX_BODY_LEN),
+        Err(DecodeError::UnknownType(0xdead))
+    );
+
+    let mut short_exit = 2_u32.to_be_bytes().to_vec();
+    short_exit.extend_from_slice(&2_u32.to_be_bytes());
+    short_exit.extend_from_slice(&[0, 0]);
+    assert_eq!(
+        decode(&short_exit, MAX_BODY_LEN),
+        Err(DecodeError::BadLength { kind: 2, len: 2 })
+    );
+
+    let mut hello = Vec::new();
+    encode(
+        &Frame::Hello {
+            min_version: 1,
+            max_version: 1,
+            capabilities: Capabilities::all(),
+            addrs: vec![ifaddr_of(Ipv4Addr::LOCALHOST.into(), 8)],
+            mtu: Some(1500),
+        },
+        &mut hello,
+    );
+    // MTU too small for any packet
+    let mtu = hello.len() - 4;
+    hello[mtu..].copy_from_slice(&20_u32.to_be_bytes());
+    assert_eq!(
+        decode(&hello, MAX_BODY_LEN),
+        Err(DecodeError::InvalidField("MTU"))
+    );
+    // MTU missing
+    hello.truncate(mtu);
+    hello[7] -= 4;
+    assert_eq!(
+        decode(&hello, MAX_BODY_LEN),
+        Err(DecodeError::BadLength {
+            kind: 0x10,
+            len: hello.len() - HEADER_LEN
+        })
+    );
+    hello[HEADER_LEN] ^= 0xff;
+    assert!(matches!(
+        decode(&hello, MAX_BODY_LEN),
+        Err(DecodeError::BadMagic(_))
+    ));
+
+    let mut reply = Vec::new();
+    encode(
+        &Frame::HelloReply {
+            version: 1,
+            capabilities: Capabilities::all(),
+            addrs: vec![ifaddr_of(Ipv6Addr::LOCALHOST.into(), 128)],
+            assigned: vec![],
+            routes: vec![],
+            dns: vec![],
+            domains: vec![],
+            mtu: None,
+        },
+        &mut reply,
+    );
+    // prefix length beyond 128
+    reply[HEADER_LEN + 19] = 129;
+    assert_eq!(
+        decode(&reply, MAX_BODY_LEN),
+        Err(DecodeError::InvalidField("netmask"))
+    );
+    // unknown address family
+    reply[HEADER_LEN + 15] = 5;
+    assert_eq!(
+        decode(&reply, MAX_BODY_LEN),
+        Err(DecodeError::InvalidField("address family"))
+    );
+    // more addresses than the body holds
+    reply[HEADER_LEN + 15] = 6;
+    reply[HEADER_LEN + 19] = 128;
+    reply[HEADER_LEN + 11] = 2;
+    assert_eq!(
+        decode(&reply, MAX_BODY_LEN),
+        Err(DecodeError::BadLength {
+            kind: 0x11,
+            len: reply.len() - HEADER_LEN
+        })
+    );
+
+    // domains end up in the resolver configuration
+    let mut reply = Vec::new();
+    encode(
+        &Frame::HelloReply {
+            version: 1,
+            capabilities: Capabilities::all(),
+            addrs: vec![],
+            assigned: vec![],
+            routes: vec![],
+            dns: vec![],
+            domains: vec!["vpn.example".to_string()],
+            mtu: None,
+        },
+        &mut reply,
+    );
+    let newline = reply.len() - 4;
+    reply[newline] = b'\n';
+    assert_eq!(
+        decode(&reply, MAX_BODY_LEN),
+        Err(DecodeError::InvalidField("domain"))
+    );
+}
+
+#[test]
+fn hello_from_later_versions() {
+    let mut hello = Vec::new();
+    encode(
+        &Frame::Hello {
+            min_version: 1,
+            max_version: 7,
+            capabilities: Capabilities::from_bits_retain(0xffff_0001),
+            addrs: vec![ifaddr_of(Ipv4Addr::LOCALHOST.into(), 8)],
+            mtu: None,
+        },
+        &mut hello,
+    );
+    // appended field, unknown to this version
+    hello.extend_from_slice(&[0xaa; 4]);
+    hello[7] += 4;
+    let (frame, len) = decode(&hello, MAX_BODY_LEN).unwrap().unwrap();
+    assert_eq!(len, hello.len());
+    let Frame::Hello {
+        max_version,
+        capabilities,
+        addrs,
+        ..
+    } = frame
+    else {
+        panic!("unexpected frame {:?}", frame);
+    };
+    assert_eq!(max_version, 7);
+    assert_eq!(capabilities & Capabilities::all(), Capabilities::KEEPALIVE);
+    assert_eq!(addrs, [ifaddr_of(Ipv4Addr::LOCALHOST.into(), 8)]);
+
+    // the MTU stays in place
+    let mut hello = Vec::new();
+    encode(
+        &Frame::Hello {
+            min_version: 1,
+            max_version: 7,
+            capabilities: Capabilities::MTU,
+            addrs: vec![],
+            mtu: Some(1400),
+        },
+        &mut hello,
+    );
+    hello.extend_from_slice(&[0xaa; 4]);
+    hello[7] += 4;
+    let (frame, _) = decode(&hello, MAX_BODY_LEN).unwrap().unwrap();
+    assert!(matches!(
+        frame,
+        Frame::Hello {
+            mtu: Some(1400),
+            ..
+        }
+    ));
+}
+
+#[test]
+fn reader_splits_stream_into_frames() {
+    let frames = [
+        Frame::Data {
+            counter: 1,
+            payload: &[7; 300],
+        },
+        Frame::Data {
+            counter: 2,
+            payload: &[9; 10],
+        },
+        Frame::Exit { reason: 0 },
+    ];
+    let mut buf = Vec::new();
+    for frame in &frames {
+        encode(frame, &mut buf);
+    }
+    // feed the reader a few bytes at a time
+    let mut reader = FrameReader::new(MAX_BODY_LEN);
+    let mut decoded = Vec::new();
+    for chunk in buf.chunks(7) {
+        assert_eq!(reader.fill(&mut Cursor::new(chunk)).unwrap(), chunk.len());
+        while let Some(frame) = reader.next_frame().unwrap() {
+            decoded.push(format!("{:?}", frame));
+        }
+    }
+    let expected: Vec<_> = frames.iter().map(|f| format!("{:?}", f)).collect();
+    assert_eq!(decoded, expected);
+    assert_eq!(reader.pending(), 0);
+}
+
+#[test]
+fn read_frame_does_not_overread() {
+    let mut buf = Vec::new();
+    encode(&Frame::HandshakeStatus { status: 0 }, &mut buf);
+    let first = buf.len();
+    encode(&Frame::Exit { reason: 0 }, &mut buf);
+    let mut cursor = Cursor::new(buf);
+    let mut reader = FrameReader::new(64);
+    assert_eq!(
+        reader.read_frame(&mut cursor).unwrap(),
+        Frame::HandshakeStatus { status: 0 }
+    );
+    assert_eq!(cursor.position() as usize, first);
+}
@@ -0,0 +1,348 @@
+mod common;
+
+use common::ifaddr_of;
+use rust_tcp_vpn::channel::Channel;
+use rust_tcp_vpn::flows::{FlowConfig, FlowExit, handle_flow};
+use rust_tcp_vpn::protocol::Frame;
+use rust_tcp_vpn::routing::{RoutingTable, destination, route_packets};
+use rust_tcp_vpn::tunif::{IfConfig, IfKind, MacAddr, TunDevice, VNET_HDR_LEN};
+use std::fs::File;
+use std::io::Write;
+use std::net::{Ipv4Addr, Ipv6Addr, TcpListener, TcpStream};
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixDatagram;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const CLIENT_A: Ipv4Addr = Ipv4Addr::new(10, 8, 0, 2);
+const CLIENT_B: Ipv4Addr = Ipv4Addr::new(10, 8, 0, 3);
+
+// smallest IPv4 header, enough to be routed
+fn ipv4_packet(dst: Ipv4Addr, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0; 20];
+    packet[0] = 0x45;
+    packet[16..20].copy_from_slice(&dst.octets());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+// kernel side of a fake TUN, packets sent to it can be read from the
+// returned socket
+fn fake_tun() -> (File, UnixDatagram) {
+    let (tun, app) = UnixDatagram::pair().unwrap();
+    app.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    (File::from(OwnedFd::from(tun)), app)
+}
+
+#[test]
+fn destination_of_packets() {
+    let packet = ipv4_packet(CLIENT_A, b"data");
+    assert_eq!(destination(&packet), Some(CLIENT_A.into()));
+
+    let dst = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2);
+    let mut packet = vec![0; 40];
+    packet[0] = 0x60;
+    packet[24..40].copy_from_slice(&dst.octets());
+    assert_eq!(destination(&packet), Some(dst.into()));
+
+    // truncated header, unknown version
+    assert_eq!(destination(&packet[..30]), None);
+    assert_eq!(destination(&[0x25; 40]), None);
+    assert_eq!(destination(&[]), None);
+}
+
+#[test]
+fn attach_rejects_used_address() {
+    let table = RoutingTable::new(false, &IfConfig::default());
+    let (tun, _app) = fake_tun();
+    let session = table
+        .attach(&[ifaddr_of(CLIENT_A.into(), 24)], tun.try_clone().unwrap())
+        .unwrap();
+    assert!(
+        table
+            .attach(&[ifaddr_of(CLIENT_A.into(), 24)], tun.try_clone().unwrap())
+            .is_err()
+    );
+    // dual stack client sharing the IPv4 address
+    let addrs = [
+        ifaddr_of(Ipv6Addr::LOCALHOST.into(), 64),
+        ifaddr_of(CLIENT_A.into(), 24),
+    ];
+    assert!(table.attach(&addrs, tun.try_clone().unwrap()).is_err());
+    // the address is released with the session
+    drop(session);
+    assert!(table.attach(&[ifaddr_of(CLIENT_A.into(), 24)], tun).is_ok());
+}
+
+#[test]
+fn forward_reaches_owner_only() {
+    let table = RoutingTable::new(false, &IfConfig::default());
+    let (tun, _app) = fake_tun();
+    let mut a = table
+        .attach(&[ifaddr_of(CLIENT_A.into(), 24)], tun.try_clone().unwrap())
+        .unwrap();
+    let _b = table
+        .attach(&[ifaddr_of(CLIENT_B.into(), 24)], tun)
+        .unwrap();
+    let packet = ipv4_packet(CLIENT_A, b"for a");
+    assert!(table.forward(&packet));
+    let mut buf = [0; 64];
+    assert_eq!(a.recv(&mut buf).unwrap(), packet.len());
+    assert_eq!(&buf[..packet.len()], packet);
+    // nobody owns it
+    assert!(!table.forward(&ipv4_packet(Ipv4Addr::new(10, 8, 0, 9), b"lost")));
+}
+
+#[test]
+fn offload_header_is_skipped() {
+    let offload = IfConfig {
+        offload: true,
+        ..IfConfig::default()
+    };
+    let table = RoutingTable::new(false, &offload);
+    let (tun, _app) = fake_tun();
+    let mut a = table
+        .attach(&[ifaddr_of(CLIENT_A.into(), 24)], tun)
+        .unwrap();
+    // virtio-net header of a packet without offload
+    let packet = [vec![0; VNET_HDR_LEN], ipv4_packet(CLIENT_A, b"for a")].concat();
+    assert!(table.forward(&packet));
+    let mut buf = [0; 64];
+    assert_eq!(a.recv(&mut buf).unwrap(), packet.len());
+    assert!(!table.forward(&ipv4_packet(CLIENT_A, b"no header")));
+}
+
+// server side of a session of the shared TUN
+struct Client {
+    remote: Channel<TcpStream>,
+    stop: File,
+    handle: JoinHandle<anyhow::Result<FlowExit>>,
+}
+
+fn connect_client(table: &std::sync::Arc<RoutingTable>, tun: &File, addr: Ipv4Addr) -> Client {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let local = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (remote, _) = listener.accept().unwrap();
+    let mut iffile = table
+        .attach(&[ifaddr_of(addr.into(), 24)], tun.try_clone().unwrap())
+        .unwrap();
+    let (stop_r, stop_w) = nix::unistd::pipe().unwrap();
+    let mut stop: File = stop_r.into();
+    let handle = thread::spawn(move || {
+        let mut channel = Channel::new(local, 64);
+        handle_flow(&mut channel, &mut iffile, &mut stop, &FlowConfig::default())
+    });
+    Client {
+        remote: Channel::new(remote, 4096),
+        stop: stop_w.into(),
+        handle,
+    }
+}
+
+#[test]
+fn sessions_share_one_tun() {
+    let table = RoutingTable::new(false, &IfConfig::default());
+    let (tun, app) = UnixDatagram::pair().unwrap();
+    app.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let tun = File::from(OwnedFd::from(tun));
+    let mut reader = tun.try_clone().unwrap();
+    let router = table.clone();
+    thread::spawn(move || route_packets(&mut reader, &router));
+
+    let mut a = connect_client(&table, &tun, CLIENT_A);
+    let mut b = connect_client(&table, &tun, CLIENT_B);
+
+    // every session injects its packets into the shared TUN
+    let mut buf = [0; 64];
+    for (client, payload) in [(&mut a, b"from a"), (&mut b, b"from b")] {
+        client
+            .remote
+            .send(&Frame::Data {
+                counter: 1,
+                payload,
+            })
+            .unwrap();
+        assert_eq!(app.recv(&mut buf).unwrap(), payload.len());
+        assert_eq!(&buf[..payload.len()], payload);
+    }
+
+    // packets read from the TUN go to the session owning the destination
+    let to_b = ipv4_packet(CLIENT_B, b"to b");
+    let to_a = ipv4_packet(CLIENT_A, b"to a");
+    app.send(&to_b).unwrap();
+    app.send(&to_a).unwrap();
+    assert_eq!(
+        b.remote.recv().unwrap(),
+        Frame::Data {
+            counter: 1,
+            payload: &to_b
+        }
+    );
+    assert_eq!(
+        a.remote.recv().unwrap(),
+        Frame::Data {
+            counter: 1,
+            payload: &to_a
+        }
+    );
+
+    // sessions terminate on their own, releasing their address
+    a.stop.write_all(&[1]).unwrap();
+    assert_eq!(a.handle.join().unwrap().unwrap(), FlowExit::Local);
+    assert_eq!(a.remote.recv().unwrap(), Frame::Exit { reason: 0 });
+    assert!(!table.forward(&to_a));
+    assert!(table.forward(&to_b));
+    b.stop.write_all(&[1]).unwrap();
+    assert_eq!(b.handle.join().unwrap().unwrap(), FlowExit::Local);
+}
+
+#[test]
+fn every_queue_is_routed() {
+    let table = RoutingTable::new(false, &IfConfig::default());
+    // the kernel spreads packets over the queues of a multi-queue TUN
+    let queues: Vec<_> = (0..3).map(|_| fake_tun()).collect();
+    for (queue, _) in &queues {
+        let mut reader = queue.try_clone().unwrap();
+        let router = table.clone();
+        thread::spawn(move || route_packets(&mut reader, &router));
+    }
+    let mut a = table
+        .attach(
+            &[ifaddr_of(CLIENT_A.into(), 24)],
+            queues[1].0.try_clone().unwrap(),
+        )
+        .unwrap();
+    let mut buf = [0; 64];
+    for (i, (_, app)) in queues.iter().enumerate() {
+        let packet = ipv4_packet(CLIENT_A, &[i as u8]);
+        app.send(&packet).unwrap();
+        assert_eq!(a.recv(&mut buf).unwrap(), packet.len());
+        assert_eq!(&buf[..packet.len()], packet);
+    }
+    // packets of the session enter its own queue
+    a.send(b"from a").unwrap();
+    assert_eq!(queues[1].1.recv(&mut buf).unwrap(), 6);
+}
+
+// client A sends a packet to client B, then one to the server
+fn a_to_b(client_to_client: bool) -> (Client, Client, UnixDatagram, Vec<u8>) {
+    let table = RoutingTable::new(client_to_client, &IfConfig::default());
+    let (tun, app) = fake_tun();
+    let mut a = connect_client(&table, &tun, CLIENT_A);
+    let b = connect_client(&table, &tun, CLIENT_B);
+    let to_b = ipv4_packet(CLIENT_B, b"to b");
+    let to_server = ipv4_packet(Ipv4Addr::new(10, 8, 0, 1), b"to server");
+    for (counter, payload) in [(1, &to_b), (2, &to_server)] {
+        a.remote.send(&Frame::Data { counter, payload }).unwrap();
+    }
+    // the packet for B never enters the TUN
+    let mut buf = [0; 64];
+    assert_eq!(app.recv(&mut buf).unwrap(), to_server.len());
+    assert_eq!(&buf[..to_server.len()], to_server);
+    (a, b, app, to_b)
+}
+
+// return the client end of the connection
+fn stop(mut client: Client) -> Channel<TcpStream> {
+    client.stop.write_all(&[1]).unwrap();
+    assert_eq!(client.handle.join().unwrap().unwrap(), FlowExit::Local);
+    client.remote
+}
+
+#[test]
+fn client_to_client_forwarding() {
+    let (a, mut b, _app, to_b) = a_to_b(true);
+    assert_eq!(
+        b.remote.recv().unwrap(),
+        Frame::Data {
+            counter: 1,
+            payload: &to_b
+        }
+    );
+    stop(a);
+    stop(b);
+}
+
+#[test]
+fn client_to_client_forbidden() {
+    let (a, b, _app, _) = a_to_b(false);
+    stop(a);
+    // nothing but the goodbye reached B
+    assert_eq!(stop(b).recv().unwrap(), Frame::Exit { reason: 0 });
+}
+
+const MAC_A: MacAddr = MacAddr([2, 0, 0, 0, 0, 0xa]);
+const MAC_B: MacAddr = MacAddr([2, 0, 0, 0, 0, 0xb]);
+const BROADCAST: MacAddr = MacAddr([0xff; 6]);
+
+fn ethernet_frame(dst: MacAddr, src: MacAddr, payload: &[u8]) -> Vec<u8> {
+    let mut frame = [dst.0, src.0].concat();
+    // IPv4 EtherType
+    frame.extend([0x08, 0x00]);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[test]
+fn mac_address_text() {
+    let mac: MacAddr = "02:00:00:00:00:0a".parse().unwrap();
+    assert_eq!(mac, MAC_A);
+    assert_eq!(mac.to_string(), "02:00:00:00:00:0a");
+    assert!(BROADCAST.is_multicast() && !MAC_A.is_multicast());
+    for text in [
+        "02:00:00:00:00",
+        "02:00:00:00:00:0a:0b",
+        "02:00:00:00:00:a",
+        "zz:00:00:00:00:0a",
+    ] {
+        assert!(text.parse::<MacAddr>().is_err(), "{}", text);
+    }
+}
+
+#[test]
+fn tap_sessions_are_switched() {
+    let tap = IfConfig {
+        kind: IfKind::Tap,
+        ..IfConfig::default()
+    };
+    let table = RoutingTable::new(false, &tap);
+    let (tun, app) = fake_tun();
+    let mut a = table
+        .attach(&[ifaddr_of(CLIENT_A.into(), 24)], tun.try_clone().unwrap())
+        .unwrap();
+    let mut b = table
+        .attach(&[ifaddr_of(CLIENT_B.into(), 24)], tun)
+        .unwrap();
+    let mut buf = [0; 64];
+
+    // unknown destinations reach every session
+    let unknown = ethernet_frame(MAC_A, MAC_B, b"unknown");
+    assert!(table.forward(&unknown));
+    assert_eq!(a.recv(&mut buf).unwrap(), unknown.len());
+    assert_eq!(b.recv(&mut buf).unwrap(), unknown.len());
+
+    // A is learned from its broadcast, which enters the TAP only
+    let hello = ethernet_frame(BROADCAST, MAC_A, b"hello");
+    a.send(&hello).unwrap();
+    assert_eq!(app.recv(&mut buf).unwrap(), hello.len());
+    // B cannot capture the address of A
+    b.send(&ethernet_frame(BROADCAST, MAC_A, b"spoof")).unwrap();
+    assert_eq!(app.recv(&mut buf).unwrap(), hello.len());
+
+    // only A gets the frames for A, B gets the next broadcast first
+    let to_a = ethernet_frame(MAC_A, MAC_B, b"to a");
+    assert!(table.forward(&to_a));
+    let everyone = ethernet_frame(BROADCAST, MAC_B, b"everyone");
+    assert!(table.forward(&everyone));
+    assert_eq!(a.recv(&mut buf).unwrap(), to_a.len());
+    assert_eq!(&buf[..to_a.len()], to_a);
+    assert_eq!(b.recv(&mut buf).unwrap(), everyone.len());
+    assert_eq!(&buf[..everyone.len()], everyone);
+
+    // forgotten with the session
+    drop(a);
+    assert!(table.forward(&to_a));
+    assert_eq!(b.recv(&mut buf).unwrap(), to_a.len());
+    // too short to be switched
+    assert!(!table.forward(&to_a[..12]));
+}
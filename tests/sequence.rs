@@ -0,0 +1,51 @@
+use rust_tcp_vpn::sequence::{SeqStats, SeqTracker, Verdict};
+
+#[test]
+fn in_order_counters() {
+    let mut tracker = SeqTracker::new();
+    for counter in 1..=1000 {
+        assert_eq!(tracker.check(counter), Verdict::InOrder);
+    }
+    assert_eq!(
+        *tracker.stats(),
+        SeqStats {
+            received: 1000,
+            ..SeqStats::default()
+        }
+    );
+}
+
+#[test]
+fn gaps_and_late_frames() {
+    let mut tracker = SeqTracker::new();
+    assert_eq!(tracker.check(1), Verdict::InOrder);
+    // 2, 3 and 4 missing
+    assert_eq!(tracker.check(5), Verdict::InOrder);
+    assert_eq!(tracker.stats().lost, 3);
+    // 3 arrives late
+    assert_eq!(tracker.check(3), Verdict::Late);
+    assert_eq!(
+        *tracker.stats(),
+        SeqStats {
+            received: 3,
+            lost: 2,
+            reordered: 1,
+            replayed: 0,
+        }
+    );
+}
+
+#[test]
+fn duplicates_and_replays() {
+    let mut tracker = SeqTracker::new();
+    assert_eq!(tracker.check(0), Verdict::Replay);
+    for counter in 1..=10 {
+        tracker.check(counter);
+    }
+    assert_eq!(tracker.check(10), Verdict::Replay);
+    assert_eq!(tracker.check(4), Verdict::Replay);
+    // far beyond the window cannot be told apart from a replay
+    assert_eq!(tracker.check(200), Verdict::InOrder);
+    assert_eq!(tracker.check(11), Verdict::Replay);
+    assert_eq!(tracker.stats().replayed, 4);
+}
@@ -0,0 +1,78 @@
+use rust_tcp_vpn::channel::Channel;
+use rust_tcp_vpn::handshake::{Endpoint, HANDSHAKE_MAX_LEN, Pushed, handler_client_handshake};
+use rust_tcp_vpn::parsing::Security;
+use rust_tcp_vpn::server::{Handshaker, Session, accept_sessions};
+use rust_tcp_vpn::tunif::{DEFAULT_MTU, IfAddr, IfKind};
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 8, 0, 1);
+const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 8, 0, 2);
+
+fn start(timeout: Option<Duration>) -> (SocketAddr, Receiver<Session>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let sessions = accept_sessions(
+        listener,
+        Handshaker {
+            local: Endpoint {
+                addrs: vec![IfAddr::new(IpAddr::V4(SERVER), 24).unwrap()],
+                kind: IfKind::Tun,
+                offload: false,
+                mtu: DEFAULT_MTU,
+                host_config: true,
+            },
+            pool: None,
+            pushed: Pushed::default(),
+            security: Security::default(),
+            timeout,
+        },
+    );
+    (addr, sessions)
+}
+
+fn client(addr: SocketAddr) -> thread::JoinHandle<Channel<TcpStream>> {
+    thread::spawn(move || {
+        let sock = TcpStream::connect(addr).unwrap();
+        let mut channel = Channel::new(sock, HANDSHAKE_MAX_LEN);
+        let local = Endpoint {
+            addrs: vec![IfAddr::new(IpAddr::V4(CLIENT), 24).unwrap()],
+            kind: IfKind::Tun,
+            offload: false,
+            mtu: DEFAULT_MTU,
+            host_config: true,
+        };
+        handler_client_handshake(&mut channel, &local, None, None).unwrap();
+        channel
+    })
+}
+
+#[test]
+fn silent_client_does_not_block_others() {
+    let (addr, sessions) = start(Some(Duration::from_secs(30)));
+    // connects and never says a word
+    let _silent = TcpStream::connect(addr).unwrap();
+    let _client = client(addr).join().unwrap();
+    let session = sessions.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(session.peer.ip().is_loopback());
+}
+
+#[test]
+fn stalled_handshake_is_dropped() {
+    let (addr, sessions) = start(Some(Duration::from_millis(200)));
+    let start = Instant::now();
+    let mut silent = TcpStream::connect(addr).unwrap();
+    silent
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    // server closes the connection once the deadline is over
+    assert_eq!(silent.read(&mut [0; 16]).unwrap(), 0);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert!(sessions.try_recv().is_err());
+    // the server keeps serving
+    let _client = client(addr).join().unwrap();
+    assert!(sessions.recv_timeout(Duration::from_secs(5)).is_ok());
+}
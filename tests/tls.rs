@@ -0,0 +1,329 @@
+mod common;
+
+use common::{FakeTun, temp_file};
+use rcgen::{BasicConstraints, CertificateParams, CertifiedKey, IsCa, Issuer, KeyPair};
+use rust_tcp_vpn::channel::Channel;
+use rust_tcp_vpn::flows::{FlowConfig, FlowExit, handle_flow};
+use rust_tcp_vpn::protocol::Frame;
+use rust_tcp_vpn::psk::{self, Psk};
+use rust_tcp_vpn::tls::{TlsConfig, TlsOptions, Transport, fingerprint, parse_fingerprint};
+use std::fs::File;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+struct Identity {
+    cert: PathBuf,
+    key: PathBuf,
+    fingerprint: [u8; 32],
+}
+
+fn save(name: &str, certified: CertifiedKey<KeyPair>) -> Identity {
+    Identity {
+        cert: temp_file(&format!("{}.crt", name), &certified.cert.pem()),
+        key: temp_file(
+            &format!("{}.key", name),
+            &certified.signing_key.serialize_pem(),
+        ),
+        fingerprint: fingerprint(certified.cert.der()),
+    }
+}
+
+fn self_signed(name: &str) -> Identity {
+    save(
+        name,
+        rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap(),
+    )
+}
+
+// CA certificate file, plus a certificate for "localhost" issued by it
+fn issued(name: &str) -> (PathBuf, Identity) {
+    let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca_key = KeyPair::generate().unwrap();
+    let ca_cert = params.self_signed(&ca_key).unwrap();
+    let ca = temp_file(&format!("{}-ca.crt", name), &ca_cert.pem());
+    let issuer = Issuer::new(params, ca_key);
+    let key = KeyPair::generate().unwrap();
+    let cert = CertificateParams::new(vec!["localhost".to_string()])
+        .unwrap()
+        .signed_by(&key, &issuer)
+        .unwrap();
+    let identity = save(
+        name,
+        CertifiedKey {
+            cert,
+            signing_key: key,
+        },
+    );
+    (ca, identity)
+}
+
+fn identity_opts(identity: &Identity) -> TlsOptions {
+    TlsOptions {
+        cert: Some(identity.cert.clone()),
+        key: Some(identity.key.clone()),
+        ..TlsOptions::default()
+    }
+}
+
+// connect both ends, exchange a data frame each way if both handshakes succeed
+fn exchange(server: TlsConfig, client: TlsConfig) -> bool {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = thread::spawn(move || {
+        let sock = TcpStream::connect(addr).unwrap();
+        let mut channel = Channel::new(Transport::new(sock, Some(&client))?, 64);
+        channel.send(&Frame::Data {
+            counter: 1,
+            payload: b"ping",
+        })?;
+        let Frame::Data { payload, .. } = channel.recv()? else {
+            anyhow::bail!("unexpected frame");
+        };
+        assert_eq!(payload, b"pong");
+        Ok(())
+    });
+    let (sock, _) = listener.accept().unwrap();
+    let server = (|| {
+        let mut channel = Channel::new(Transport::new(sock, Some(&server))?, 64);
+        let Frame::Data { payload, .. } = channel.recv()? else {
+            anyhow::bail!("unexpected frame");
+        };
+        assert_eq!(payload, b"ping");
+        channel.send(&Frame::Data {
+            counter: 1,
+            payload: b"pong",
+        })
+    })();
+    let client = client.join().unwrap();
+    server.is_ok() && client.is_ok()
+}
+
+#[test]
+fn ca_verified_server() {
+    let (ca, server) = issued("ca-server");
+    let client_opts = TlsOptions {
+        ca: Some(ca),
+        ..TlsOptions::default()
+    };
+    assert!(exchange(
+        TlsConfig::server(&identity_opts(&server)).unwrap(),
+        TlsConfig::client(&client_opts, "localhost").unwrap(),
+    ));
+}
+
+#[test]
+fn server_name_mismatch() {
+    let (ca, server) = issued("ca-name");
+    let client_opts = TlsOptions {
+        ca: Some(ca),
+        server_name: Some("vpn.example.com".to_string()),
+        ..TlsOptions::default()
+    };
+    assert!(!exchange(
+        TlsConfig::server(&identity_opts(&server)).unwrap(),
+        TlsConfig::client(&client_opts, "127.0.0.1").unwrap(),
+    ));
+}
+
+#[test]
+fn pinned_server() {
+    let server = self_signed("pin-server");
+    let client_opts = TlsOptions {
+        pins: vec![server.fingerprint],
+        ..TlsOptions::default()
+    };
+    // the name does not matter once the certificate is pinned
+    assert!(exchange(
+        TlsConfig::server(&identity_opts(&server)).unwrap(),
+        TlsConfig::client(&client_opts, "127.0.0.1").unwrap(),
+    ));
+}
+
+#[test]
+fn pin_mismatch() {
+    let server = self_signed("pin-mismatch");
+    let other = self_signed("pin-other");
+    let client_opts = TlsOptions {
+        pins: vec![other.fingerprint],
+        ..TlsOptions::default()
+    };
+    assert!(!exchange(
+        TlsConfig::server(&identity_opts(&server)).unwrap(),
+        TlsConfig::client(&client_opts, "localhost").unwrap(),
+    ));
+}
+
+#[test]
+fn mutual_pinning() {
+    let server = self_signed("mtls-server");
+    let client = self_signed("mtls-client");
+    let server_opts = TlsOptions {
+        pins: vec![client.fingerprint],
+        ..identity_opts(&server)
+    };
+    let client_opts = TlsOptions {
+        pins: vec![server.fingerprint],
+        ..identity_opts(&client)
+    };
+    assert!(exchange(
+        TlsConfig::server(&server_opts).unwrap(),
+        TlsConfig::client(&client_opts, "localhost").unwrap(),
+    ));
+}
+
+#[test]
+fn missing_client_certificate() {
+    let server = self_signed("nocert-server");
+    let client = self_signed("nocert-client");
+    let server_opts = TlsOptions {
+        pins: vec![client.fingerprint],
+        ..identity_opts(&server)
+    };
+    let client_opts = TlsOptions {
+        pins: vec![server.fingerprint],
+        ..TlsOptions::default()
+    };
+    assert!(!exchange(
+        TlsConfig::server(&server_opts).unwrap(),
+        TlsConfig::client(&client_opts, "localhost").unwrap(),
+    ));
+}
+
+#[test]
+fn psk_bound_to_tls_session() {
+    // both endpoints export the same value, so their proofs match
+    let server = self_signed("psk-server");
+    let client_opts = TlsOptions {
+        pins: vec![server.fingerprint],
+        ..TlsOptions::default()
+    };
+    let server = TlsConfig::server(&identity_opts(&server)).unwrap();
+    let client = TlsConfig::client(&client_opts, "localhost").unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = thread::spawn(move || {
+        let sock = TcpStream::connect(addr).unwrap();
+        let mut channel = Channel::new(Transport::new(sock, Some(&client))?, 64);
+        let psk = Psk::new(b"correct horse battery staple").unwrap();
+        psk::client_auth(&mut channel, &psk)?;
+        channel.binding()
+    });
+    let (sock, _) = listener.accept().unwrap();
+    let mut channel = Channel::new(Transport::new(sock, Some(&server)).unwrap(), 64);
+    let psk = Psk::new(b"correct horse battery staple").unwrap();
+    psk::server_auth(&mut channel, &psk).unwrap();
+    let binding = channel.binding().unwrap();
+    assert!(!binding.is_empty());
+    assert_eq!(client.join().unwrap().unwrap(), binding);
+}
+
+#[test]
+fn fingerprint_parsing() {
+    let text = "00:11:22:33:44:55:66:77:88:99:aa:bb:cc:dd:ee:ff:00:11:22:33:44:55:66:77:88:99:AA:BB:CC:DD:EE:FF";
+    let pin = parse_fingerprint(text).unwrap();
+    assert_eq!(pin[..4], [0x00, 0x11, 0x22, 0x33]);
+    assert_eq!(pin[31], 0xff);
+    assert_eq!(parse_fingerprint(&text.replace(':', "")).unwrap(), pin);
+    assert!(parse_fingerprint("0011").is_err());
+    assert!(parse_fingerprint(&"zz".repeat(32)).is_err());
+}
+
+// flow of a TLS server and the client channel, the raw client socket, the
+// application side of the server interface and the signal pipe
+#[allow(clippy::type_complexity)]
+fn tls_flow() -> (
+    Channel<Transport>,
+    TcpStream,
+    UnixDatagram,
+    File,
+    thread::JoinHandle<anyhow::Result<FlowExit>>,
+) {
+    let server = self_signed("flow-server");
+    let client_opts = TlsOptions {
+        pins: vec![server.fingerprint],
+        ..TlsOptions::default()
+    };
+    let server = TlsConfig::server(&identity_opts(&server)).unwrap();
+    let client = TlsConfig::client(&client_opts, "localhost").unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = thread::spawn(move || {
+        let sock = TcpStream::connect(addr).unwrap();
+        let raw = sock.try_clone().unwrap();
+        let channel = Channel::new(Transport::new(sock, Some(&client)).unwrap(), 64);
+        (channel, raw)
+    });
+    let (sock, _) = listener.accept().unwrap();
+    let mut server = Channel::new(Transport::new(sock, Some(&server)).unwrap(), 64);
+    let (client, raw) = client.join().unwrap();
+    // fail rather than hang
+    raw.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    let (tun, app) = UnixDatagram::pair().unwrap();
+    let (sig_r, sig_w) = nix::unistd::pipe().unwrap();
+    let mut sigfile: File = sig_r.into();
+    let flow = thread::spawn(move || {
+        let mut tun = FakeTun(tun);
+        handle_flow(&mut server, &mut tun, &mut sigfile, &FlowConfig::default())
+    });
+    (client, raw, app, sig_w.into(), flow)
+}
+
+#[test]
+fn tls_flow_carries_packets() {
+    let (mut client, _raw, app, mut sigw, flow) = tls_flow();
+
+    // a single burst, so several frames share TLS records
+    let big = vec![0xab; 3000];
+    for counter in 1..=20 {
+        client
+            .write(&Frame::Data {
+                counter,
+                payload: &big[..counter as usize * 100],
+            })
+            .unwrap();
+    }
+    client.flush().unwrap();
+    let mut pkt = vec![0; 4096];
+    for counter in 1..=20 {
+        assert_eq!(app.recv(&mut pkt).unwrap(), counter * 100);
+    }
+    app.send(&[1, 2, 3]).unwrap();
+    assert_eq!(
+        client.recv().unwrap(),
+        Frame::Data {
+            counter: 1,
+            payload: &[1, 2, 3]
+        }
+    );
+
+    sigw.write_all(&[1]).unwrap();
+    assert_eq!(flow.join().unwrap().unwrap(), FlowExit::Local);
+    assert_eq!(client.recv().unwrap(), Frame::Exit { reason: 0 });
+}
+
+#[test]
+fn partial_record_does_not_stall_flow() {
+    let (mut client, mut raw, app, mut sigw, flow) = tls_flow();
+    // application data record header announcing 64 bytes, then 3 of them
+    raw.write_all(&[0x17, 0x03, 0x03, 0x00, 0x40, 1, 2, 3])
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+    // the other direction keeps going while the record is incomplete
+    app.send(&[1, 2, 3]).unwrap();
+    assert_eq!(
+        client.recv().unwrap(),
+        Frame::Data {
+            counter: 1,
+            payload: &[1, 2, 3]
+        }
+    );
+
+    sigw.write_all(&[1]).unwrap();
+    assert_eq!(flow.join().unwrap().unwrap(), FlowExit::Local);
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::ifaddr;
+use rust_tcp_vpn::netlink::Link;
+use rust_tcp_vpn::tunif::{IfAddr, IfConfig, check_attachable};
+
+fn tun_link(mtu: u32) -> Link {
+    Link {
+        index: 7,
+        mtu: Some(mtu),
+        kind: Some("tun".to_string()),
+    }
+}
+
+#[test]
+fn attachable_interface() {
+    let addrs = [ifaddr("10.8.0.2/24"), ifaddr("fd00::2/64")];
+    let config = IfConfig {
+        mtu: 1400,
+        attach: true,
+        ..IfConfig::default()
+    };
+    // in any order, with a smaller MTU
+    let link_addrs = [addrs[1], addrs[0]];
+    assert!(check_attachable("ptun0", &tun_link(1400), &link_addrs, &addrs, &config).is_ok());
+    assert!(check_attachable("ptun0", &tun_link(1280), &link_addrs, &addrs, &config).is_ok());
+}
+
+#[test]
+fn unattachable_interfaces() {
+    let addrs = [ifaddr("10.8.0.2/24")];
+    let config = IfConfig {
+        mtu: 1400,
+        attach: true,
+        ..IfConfig::default()
+    };
+    let check = |link: &Link, link_addrs: &[IfAddr]| {
+        check_attachable("ptun0", link, link_addrs, &addrs, &config)
+    };
+    // larger packets would be dropped
+    assert!(check(&tun_link(1500), &addrs).is_err());
+    // not a TUN or TAP interface
+    let physical = Link {
+        kind: None,
+        ..tun_link(1400)
+    };
+    assert!(check(&physical, &addrs).is_err());
+    let veth = Link {
+        kind: Some("veth".to_string()),
+        ..tun_link(1400)
+    };
+    assert!(check(&veth, &addrs).is_err());
+    // stale addresses
+    assert!(check(&tun_link(1400), &[ifaddr("10.8.0.3/24")]).is_err());
+    assert!(check(&tun_link(1400), &[ifaddr("10.8.0.2/25")]).is_err());
+    assert!(check(&tun_link(1400), &[]).is_err());
+    assert!(check(&tun_link(1400), &[addrs[0], ifaddr("fd00::2/64")]).is_err());
+    // the MAC address is left alone
+    let tap = IfConfig {
+        mac: Some("02:00:00:00:00:01".parse().unwrap()),
+        ..config.clone()
+    };
+    assert!(check_attachable("ptun0", &tun_link(1400), &addrs, &addrs, &tap).is_err());
+}